@@ -1,10 +1,11 @@
 use safe_vk::{
     auto_ok,
-    extract::{Ctx, State},
+    extract::{Args, Captures, Ctx, State},
     responses::Message,
     Filter, SafeVk,
 };
 
+use serde::Deserialize;
 use serde_json::{json, Value};
 use std::{
     env,
@@ -17,7 +18,7 @@ use tokio::sync::Mutex;
 mod custom_bindings;
 use custom_bindings::*;
 
-const SERVER: &'static str = "127.0.0.1:8188";
+const SERVER: &str = "127.0.0.1:8188";
 
 #[derive(Clone)]
 pub struct AppState {
@@ -38,41 +39,40 @@ async fn randomize(State(state): State<Arc<Mutex<AppState>>>, update: Ctx<Messag
         .await?;
 }
 
+#[derive(Deserialize)]
+struct Seed {
+    n: u16,
+}
+
 #[auto_ok]
-async fn seed(State(state): State<Arc<Mutex<AppState>>>, update: Ctx<Message>) {
+async fn seed(
+    State(state): State<Arc<Mutex<AppState>>>,
+    update: Ctx<Message>,
+    Captures(Seed { n }): Captures<Seed>,
+) {
     let mut state = state.lock().await;
-    let message = &update.message.text;
-
-    if let Some(number_str) = message.strip_prefix("/seed ") {
-        if let Ok(number) = number_str.parse::<u16>() {
-            update
-                .messages()
-                .send_message(&format!("New seed: {}", number))
-                .await?;
-            state.seed = number;
-            state.randomize = false;
-        } else {
-            panic!("The string after '/seed ' is not a valid u64 number.");
-        }
-    }
+
+    update
+        .messages()
+        .send_message(&format!("New seed: {}", n))
+        .await?;
+    state.seed = n;
+    state.randomize = false;
 }
 
 #[auto_ok]
-async fn cfg(State(state): State<Arc<Mutex<AppState>>>, update: Ctx<Message>) {
+async fn cfg(
+    State(state): State<Arc<Mutex<AppState>>>,
+    update: Ctx<Message>,
+    Args((cfg,)): Args<(f32,)>,
+) {
     let mut state = state.lock().await;
-    let message = &update.message.text;
-
-    if let Some(number_str) = message.strip_prefix("/cfg ") {
-        if let Ok(number) = number_str.parse::<f32>() {
-            update
-                .messages()
-                .send_message(&format!("changed from {} to {}", state.cfg, number))
-                .await?;
-            state.cfg = number;
-        } else {
-            println!("The string after '/cfg ' is not a valid f32 number.");
-        }
-    }
+
+    update
+        .messages()
+        .send_message(&format!("changed from {} to {}", state.cfg, cfg))
+        .await?;
+    state.cfg = cfg;
 }
 
 #[auto_ok]
@@ -235,8 +235,8 @@ async fn main() {
         .command("/help", help, Filter::Strict)
         .command("/g", imagine, Filter::Sensitive)
         .command("/rnd", randomize, Filter::Flexible)
-        .command("/cfg", cfg, Filter::Sensitive)
-        .command("/seed", seed, Filter::Sensitive)
+        .command("/cfg", cfg, Filter::Prefix)
+        .command_regex(r"^/seed (?P<n>\d+)$", seed)
         .with_state(Arc::new(Mutex::new(app_state)));
 
     safe_vk::start_polling(&token, bot).await.unwrap();
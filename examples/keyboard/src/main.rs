@@ -1,8 +1,7 @@
 use safe_vk::{
     auto_ok,
-    extract::{Ctx, Update},
-    parse_response,
-    responses::{ButtonPressCallback, Message},
+    extract::{Ctx, Keyboard, Update},
+    responses::Message,
     Button, Filter, KeyboardColor, SafeVk, ShowSnackbar,
 };
 use serde::{Deserialize, Serialize};
@@ -28,18 +27,14 @@ async fn keyboard(update: Ctx<Message>) {
 }
 
 #[auto_ok]
-async fn changes(update: Ctx<Update>) {
-    if let Ok(message) = parse_response!(update.object.clone(), ButtonPressCallback<Payload>) {
-        if message.payload.button == 1 {
-            update
-                .messages()?
-                .send_message_event_answer()
-                .event_id(&message.event_id)
-                .user_id(message.user_id)
-                .event_data(ShowSnackbar::new("Thank you!"))
-                .await?;
-        }
-    }
+async fn changes(update: Ctx<Update>, Keyboard(press): Keyboard<Payload>) {
+    update
+        .messages()?
+        .send_message_event_answer()
+        .event_id(&press.event_id)
+        .user_id(press.user_id)
+        .event_data(ShowSnackbar::new("Thank you!"))
+        .await?;
 }
 
 #[tokio::main]
@@ -48,7 +43,7 @@ async fn main() {
 
     let bot = SafeVk::new()
         .command("/keyboard", keyboard, Filter::Sensitive)
-        .watch(changes);
+        .callback(PAYLOAD, changes);
 
     safe_vk::start_polling(&token, bot).await.unwrap();
 }
@@ -9,7 +9,7 @@ use serde_json::{json, Value};
 use std::{env, sync::Arc};
 use tokio::sync::Mutex;
 
-const OPEN_API: &'static str = "http://127.0.0.1:5000/v1/chat/completions";
+const OPEN_API: &str = "http://127.0.0.1:5000/v1/chat/completions";
 
 #[derive(Clone)]
 pub struct AppState {
@@ -23,7 +23,7 @@ async fn update_message(update: &Ctx<Message>, message: &str, message_id: i32, p
         .edit()
         .peer_id(peer_id)
         .conversation_message_id(message_id)
-        .message(&message)
+        .message(message)
         .await?;
 }
 
@@ -85,8 +85,7 @@ async fn answer(State(state): State<Arc<Mutex<AppState>>>, update: Ctx<Message>)
         match chunk {
             Ok(bytes) => {
                 let text = String::from_utf8_lossy(&bytes);
-                if text.starts_with("data: ") {
-                    let json_str = &text[6..]; // Strip "data: " prefix
+                if let Some(json_str) = text.strip_prefix("data: ") {
                     if let Ok(json) = serde_json::from_str::<Value>(json_str) {
                         if let Some(content) = json["choices"][0]["delta"]["content"].as_str() {
                             let tokens: Vec<&str> = content.split_whitespace().collect();
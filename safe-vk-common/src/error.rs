@@ -37,8 +37,8 @@ pub enum VkError {
     #[error("In test mode, the application must be disabled or the user must be logged in.\nAdditional details: {0}")]
     TestModeRestrictions(String),
 
-    #[error("Captcha needed. Process this challenge response as described here: (link to captcha handling).\nAdditional details: {0}")]
-    CaptchaRequired(String),
+    #[error("Captcha needed. Process this challenge response as described here: (link to captcha handling).\nAdditional details: {message}")]
+    CaptchaRequired { message: String, captcha_sid: String, captcha_img: String },
 
     #[error("Access denied. Make sure you are using the correct identifiers and that the content is available to the current user on the full version of the site.\nAdditional details: {0}")]
     AccessDenied(String),
@@ -130,7 +130,18 @@ impl VkError {
                 9 => Self::TooManySimilarActions(message),
                 10 => Self::InternalServerError(message),
                 11 => Self::TestModeRestrictions(message),
-                14 => Self::CaptchaRequired(message),
+                14 => {
+                    let field = |key: &str| match json.get(key) {
+                        Some(serde_json::Value::String(value)) => value.clone(),
+                        Some(value) if !value.is_null() => value.to_string(),
+                        _ => String::new(),
+                    };
+                    Self::CaptchaRequired {
+                        message,
+                        captcha_sid: field("captcha_sid"),
+                        captcha_img: field("captcha_img"),
+                    }
+                }
                 15 => Self::AccessDenied(message),
                 16 => Self::HttpsRequired(message),
                 17 => Self::ValidationRequired(message),
@@ -160,6 +171,48 @@ impl VkError {
             Self::UnknownError(message)
         }
     }
+
+    /// The `error_code` VK reported. Codes that aren't known to this crate are reported as
+    /// `1`, since they become [`VkError::UnknownError`].
+    pub fn code(&self) -> i32 {
+        match self {
+            Self::UnknownError(_) => 1,
+            Self::ApplicationDisabled(_) => 2,
+            Self::UnknownMethod(_) => 3,
+            Self::InvalidSignature(_) => 4,
+            Self::UserAuthorizationFailed(_) => 5,
+            Self::TooManyRequests(_) => 6,
+            Self::NoPermissionForAction(_) => 7,
+            Self::InvalidRequest(_) => 8,
+            Self::TooManySimilarActions(_) => 9,
+            Self::InternalServerError(_) => 10,
+            Self::TestModeRestrictions(_) => 11,
+            Self::CaptchaRequired { .. } => 14,
+            Self::AccessDenied(_) => 15,
+            Self::HttpsRequired(_) => 16,
+            Self::ValidationRequired(_) => 17,
+            Self::PageBlockedOrDeleted(_) => 18,
+            Self::ActionForbiddenForNonStandaloneApps(_) => 20,
+            Self::ActionAllowedOnlyForStandaloneAndOpenAPI(_) => 21,
+            Self::MethodDisabled(_) => 23,
+            Self::UserConfirmationRequired(_) => 24,
+            Self::InvalidCommunityAccessToken(_) => 27,
+            Self::InvalidApplicationAccessToken(_) => 28,
+            Self::MethodCallLimitReached(_) => 29,
+            Self::ProfileIsPrivate(_) => 30,
+            Self::MissingOrInvalidParameter(_) => 100,
+            Self::InvalidApiId(_) => 101,
+            Self::InvalidUserId(_) => 113,
+            Self::InvalidTimestamp(_) => 150,
+            Self::AlbumAccessDenied(_) => 200,
+            Self::AudioAccessDenied(_) => 201,
+            Self::GroupAccessDenied(_) => 203,
+            Self::AlbumFull(_) => 300,
+            Self::ActionForbidden(_) => 500,
+            Self::NoRightsForAdOperations(_) => 600,
+            Self::AdCabinetError(_) => 603,
+        }
+    }
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -183,9 +236,36 @@ pub enum Error {
     #[error("Reqwest error: {0}")]
     Reqwest(#[from] reqwest::Error),
 
+    /// The request didn't reach VK or VK failed to handle it: DNS failure, refused
+    /// connection, timeout or a `5xx` status. Unlike [`Error::VkApi`], these are
+    /// usually temporary and worth retrying.
+    #[error("Transport error: {0}")]
+    Transport(#[source] reqwest::Error),
+
+    /// The `execute` request that carried a batch of calls failed as a whole,
+    /// so every call in the batch gets the same error.
+    #[error("Batched request failed: {0}")]
+    BatchFailed(#[source] std::sync::Arc<Error>),
+
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+
     #[error("Dimension index {dim} exceeds the maximum allowed shape dimensions (5x10) for shape {shape:?}")]
     DimOutOfRange { shape: Shape, dim: usize },
 
+    /// Returned by a handler to decline the update, which then goes to the next route that
+    /// matches it, and to the watchers if there is none.
+    #[error("Handler declined the update")]
+    Continue,
+
+    /// An extractor couldn't be created from the update, so the handler wasn't called.
+    #[error("Update rejected by an extractor: {0}")]
+    Rejection(String),
+
+    /// Routers combined with `SafeVk::merge` or `SafeVk::nest` both have this route.
+    #[error("Route `{0}` is defined in both routers")]
+    RouteCollision(String),
+
     /// Indicates that the listener for a specific command was not found.
     #[error("Listener not found")]
     ListenerNotFound,
@@ -209,4 +289,23 @@ pub enum Error {
     InformationLost,
 }
 
+impl Error {
+    /// Returns `true` if the error was caused by the network or VK servers being unavailable.
+    pub fn is_transport(&self) -> bool {
+        matches!(self, Error::Transport(_))
+    }
+
+    /// Returns `true` if a handler declined the update with [`Error::Continue`].
+    pub fn is_continue(&self) -> bool {
+        matches!(self, Error::Continue)
+    }
+}
+
+/// Lets extractors that never fail use [`Infallible`](std::convert::Infallible) as their rejection.
+impl From<std::convert::Infallible> for Error {
+    fn from(never: std::convert::Infallible) -> Self {
+        match never {}
+    }
+}
+
 pub type Result<T> = std::result::Result<T, Error>;
@@ -40,6 +40,20 @@
 //! assert!(matchit("START",  command, filter)); // This would pass
 //! assert!(matchit("!start", command, filter)); // This would pass
 //! ```
+//!
+//! [`Filter::Prefix`](crate::util::Filter::Prefix) requires the message to start with the command, arguments can follow it:
+//!
+//! ```ignore
+//! use safe_vk::{matchit, Filter};
+//!
+//! let command = "/cfg";
+//! let filter = &Filter::Prefix;
+//!
+//! assert!(matchit("/cfg 7.5", command, filter)); // This would pass
+//! assert!(matchit("/cfg", command, filter)); // This would pass
+//! assert!(!matchit("/cfg7.5", command, filter)); // This would fail
+//! assert!(!matchit("what does /cfg do?", command, filter)); // This would fail
+//! ```
 use regex::Regex;
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
@@ -50,16 +64,43 @@ pub enum Filter {
     Flexible,
     /// Can be triggered without any symbol, and also can be uppercase
     Sensitive,
+    /// Must start with exactly the same command, arguments can follow it after whitespace
+    Prefix,
 }
 
 pub fn matchit(message: &str, command: &str, filter: &Filter) -> bool {
+    command_regex(command, filter).is_match(message)
+}
+
+/// Byte index in `message` right after the `command`, where its arguments start, or `None`
+/// if the message doesn't match.
+///
+/// ```ignore
+/// use safe_vk::{command_end, Filter};
+///
+/// assert_eq!(command_end("/seed 42", "/seed", &Filter::Sensitive), Some(5));
+/// assert_eq!(command_end("/start", "/seed", &Filter::Sensitive), None);
+/// assert_eq!(command_end("/seed 42", "/seed", &Filter::Prefix), Some(5));
+/// ```
+pub fn command_end(message: &str, command: &str, filter: &Filter) -> Option<usize> {
+    command_regex(command, filter)
+        .captures(message)
+        .and_then(|captures| captures.name("command"))
+        .map(|command| command.end())
+}
+
+fn command_regex(command: &str, filter: &Filter) -> Regex {
     let pattern = match filter {
-        Filter::Strict => format!(r"^{}$", regex::escape(command)),
-        Filter::Flexible => format!(r"(?i)^\s*[^\w\s]?{}\s*$", regex::escape(command)),
+        Filter::Strict => format!(r"^(?P<command>{})$", regex::escape(command)),
+        Filter::Flexible => format!(
+            r"(?i)^\s*[^\w\s]?(?P<command>{})\s*$",
+            regex::escape(command)
+        ),
         Filter::Sensitive => format!(
-            r"(?i)(?:^|[\W_]){}(?:[\W_]|$)",
-            regex::escape(&command.trim_start_matches(|c: char| !c.is_alphanumeric()))
+            r"(?i)(?:^|[\W_])(?P<command>{})(?:[\W_]|$)",
+            regex::escape(command.trim_start_matches(|c: char| !c.is_alphanumeric()))
         ),
+        Filter::Prefix => format!(r"^(?P<command>{})(?:\s|$)", regex::escape(command)),
     };
-    Regex::new(&pattern).unwrap().is_match(message)
+    Regex::new(&pattern).unwrap()
 }
@@ -100,7 +100,7 @@ pub fn derive_method(input: TokenStream) -> TokenStream {
 
                         fn into_future(self) -> Self::IntoFuture {
                             Box::pin(async move {
-                                let response = self.request.post(crate::VK, #method_path, &self.query, {}).await?;
+                                let response = self.request.call_method(#method_path, &self.query).await?;
                                 let parsed = parse_response!(response, #response_type)?;
                                 Ok(parsed)
                             })
@@ -135,7 +135,7 @@ pub fn derive_method(input: TokenStream) -> TokenStream {
 
                             fn into_future(self) -> Self::IntoFuture {
                                 Box::pin(async move {
-                                    let response = self.request.post(crate::VK, #method_path, &self.query, {}).await?;
+                                    let response = self.request.call_method(#method_path, &self.query).await?;
                                     let parsed = parse_response!(response, #field_type)?;
                                     Ok(parsed)
                                 })
@@ -169,7 +169,7 @@ pub fn derive_method(input: TokenStream) -> TokenStream {
 
                         fn into_future(self) -> Self::IntoFuture {
                             Box::pin(async move {
-                                let response = self.request.post(crate::VK, #method_path, &self.query, {}).await?;
+                                let response = self.request.call_method(#method_path, &self.query).await?;
                                 let parsed = parse_response!(response, #response_type)?;
                                 Ok(parsed)
                             })
@@ -183,3 +183,79 @@ pub fn derive_method(input: TokenStream) -> TokenStream {
 
     generated.into()
 }
+
+/// Implements `safe_vk::extract::FromArgs` for a struct, taking the arguments in the order of
+/// its fields. Field names are used in the usage message.
+#[proc_macro_derive(FromArgs)]
+pub fn derive_from_args(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    let struct_name = input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    let Data::Struct(data_struct) = &input.data else {
+        panic!("FromArgs can only be derived for structs");
+    };
+
+    let field_types = data_struct.fields.iter().map(|f| &f.ty).collect::<Vec<_>>();
+    let indices = 0..field_types.len();
+    let count = field_types.len();
+
+    // Tuple structs have no names, so their arguments are named after the types
+    let names = data_struct
+        .fields
+        .iter()
+        .map(|f| match &f.ident {
+            Some(ident) => quote! { #ident },
+            None => quote! {},
+        })
+        .collect::<Vec<_>>();
+    let arg_names = data_struct
+        .fields
+        .iter()
+        .zip(&field_types)
+        .map(|(f, ty)| match &f.ident {
+            Some(ident) => {
+                let name = ident.to_string();
+                quote! { #name }
+            }
+            None => quote! { <#ty as safe_vk::extract::FromArg>::placeholder() },
+        })
+        .collect::<Vec<_>>();
+
+    let construct = match &data_struct.fields {
+        Fields::Named(_) => quote! {
+            Self {
+                #(#names: <#field_types as safe_vk::extract::FromArg>::nth(args, #indices, #arg_names)?,)*
+            }
+        },
+        Fields::Unnamed(_) => quote! {
+            Self(
+                #(<#field_types as safe_vk::extract::FromArg>::nth(args, #indices, #arg_names)?,)*
+            )
+        },
+        Fields::Unit => quote! { Self },
+    };
+
+    let expanded = quote! {
+        impl #impl_generics safe_vk::extract::FromArgs for #struct_name #ty_generics #where_clause {
+            fn from_args(
+                args: &[::std::string::String],
+            ) -> ::std::result::Result<Self, safe_vk::extract::ArgsError> {
+                if args.len() > #count {
+                    return ::std::result::Result::Err(safe_vk::extract::ArgsError::TooMany { expected: #count });
+                }
+
+                ::std::result::Result::Ok(#construct)
+            }
+
+            fn usage() -> ::std::string::String {
+                let usage: [::std::string::String; #count] = [
+                    #(<#field_types as safe_vk::extract::FromArg>::usage(#arg_names),)*
+                ];
+                usage.join(" ")
+            }
+        }
+    };
+
+    TokenStream::from(expanded)
+}
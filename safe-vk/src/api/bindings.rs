@@ -3,7 +3,7 @@ use crate::{
         messages::AbstractionMessages, photos::AbstractionPhotos, users::AbstractionUsers,
         MethodBuilder,
     },
-    extract::{Ctx, Update},
+    extract::{find_peer_id, Ctx, Update},
     responses::Message,
     Result,
 };
@@ -31,7 +31,7 @@ impl Ctx<Message> {
 
 impl Ctx<Update> {
     pub fn messages(&self) -> Result<MethodBuilder> {
-        let peer_id = self.find_peer_id(&self.object)?;
+        let peer_id = find_peer_id(&self.object)?;
         Ok(<MethodBuilder as AbstractionMessages>::new(
             self.request.clone(),
             Some(peer_id),
@@ -39,7 +39,7 @@ impl Ctx<Update> {
     }
 
     pub fn photos(&self) -> Result<MethodBuilder> {
-        let peer_id = self.find_peer_id(&self.object)?;
+        let peer_id = find_peer_id(&self.object)?;
         Ok(<MethodBuilder as AbstractionPhotos>::new(
             self.request.clone(),
             Some(peer_id),
@@ -47,7 +47,7 @@ impl Ctx<Update> {
     }
 
     pub fn users(&self) -> Result<MethodBuilder> {
-        let peer_id = self.find_peer_id(&self.object)?;
+        let peer_id = find_peer_id(&self.object)?;
         Ok(<MethodBuilder as AbstractionUsers>::new(
             self.request.clone(),
             Some(peer_id),
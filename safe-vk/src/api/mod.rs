@@ -59,7 +59,7 @@ pub trait Write {
     fn arg<T: WriteQuery>(&mut self, key: &str, value: T) -> &mut Self {
         key.write_query(self);
         self.write(b"=");
-        value.write_query(self);
+        value.write_query(&mut Escaped(self));
         self.write(b"&");
         self
     }
@@ -68,7 +68,7 @@ pub trait Write {
     fn arg_fmt(&mut self, key: &str, value: impl fmt::Display) -> &mut Self {
         key.write_query(self);
         self.write(b"=");
-        self.write_fmt(value);
+        Escaped(self).write_fmt(value);
         self.write(b"&");
         self
     }
@@ -78,19 +78,40 @@ pub trait Write {
         let json = serde_json::to_string(&value).expect("Invalid JSON");
         key.write_query(self);
         self.write(b"=");
-        json.write_query(self);
+        json.write_query(&mut Escaped(self));
         self.write(b"&");
         self
     }
 }
 
+/// Escapes `%`, `&` and `=` in values, so a text containing them doesn't break the query.
+struct Escaped<'a, W: ?Sized>(&'a mut W);
+
+impl<W: Write + ?Sized> Write for Escaped<'_, W> {
+    fn write(&mut self, arg: &[u8]) {
+        let mut start = 0;
+        for (i, byte) in arg.iter().enumerate() {
+            let escaped: &[u8] = match byte {
+                b'%' => b"%25",
+                b'&' => b"%26",
+                b'=' => b"%3D",
+                _ => continue,
+            };
+            self.0.write(&arg[start..i]);
+            self.0.write(escaped);
+            start = i + 1;
+        }
+        self.0.write(&arg[start..]);
+    }
+}
+
 pub trait WriteQuery: Sized {
     fn write_query<W>(&self, out: &mut W)
     where
         W: Write + ?Sized;
 }
 
-impl<'a> WriteQuery for &'a str {
+impl WriteQuery for &str {
     fn write_query<W>(&self, out: &mut W)
     where
         W: Write + ?Sized,
@@ -198,6 +219,7 @@ macro_rules! _define_abstraction {
         }
     ) => {
         pub trait $trait_name {
+            #[allow(clippy::new_ret_no_self)]
             fn new(request: Arc<RequestBuilder>, peer_id: Option<i64>) -> $builder where Self: Sized;
 
             $(
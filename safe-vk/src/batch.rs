@@ -0,0 +1,274 @@
+use crate::{reqwest_ext::query_params, Error, RequestBuilder, Result, VkError};
+use serde_json::{json, Map, Value};
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::sync::oneshot;
+
+/// VK doesn't allow more calls in a single `execute`.
+const MAX_CALLS: usize = 25;
+
+/// Coalesces API calls made within `window` into a single
+/// [`execute`](https://dev.vk.com/en/method/execute) request, see
+/// [`RequestConfig::batch`](crate::RequestConfig::batch).
+#[derive(Debug)]
+pub(crate) struct Batcher {
+    window: Duration,
+    pending: Mutex<Vec<Call>>,
+}
+
+#[derive(Debug)]
+struct Call {
+    method: String,
+    params: Map<String, Value>,
+    reply: oneshot::Sender<Result<Value>>,
+}
+
+impl Batcher {
+    pub(crate) fn new(window: Duration) -> Self {
+        Self {
+            window,
+            pending: Mutex::default(),
+        }
+    }
+
+    /// Queues a call of `method` with `query` parameters and waits for its own result.
+    pub(crate) async fn call(
+        self: &Arc<Self>,
+        request: &RequestBuilder,
+        method: &str,
+        query: &str,
+    ) -> Result<Value> {
+        let (reply, result) = oneshot::channel();
+
+        let full = {
+            let mut pending = self.pending.lock().unwrap();
+            pending.push(Call {
+                method: method.to_owned(),
+                params: params(query),
+                reply,
+            });
+
+            match pending.len() {
+                // The first call of a batch sends it once the window is over, unless it
+                // fills up earlier. Whatever is pending at that moment goes together
+                1 => {
+                    let batcher = Arc::clone(self);
+                    let request = request.clone();
+                    tokio::spawn(async move {
+                        tokio::time::sleep(batcher.window).await;
+                        let calls = std::mem::take(&mut *batcher.pending.lock().unwrap());
+                        execute(&request, calls).await;
+                    });
+                    None
+                }
+                MAX_CALLS => Some(std::mem::take(&mut *pending)),
+                _ => None,
+            }
+        };
+
+        if let Some(calls) = full {
+            let request = request.clone();
+            tokio::spawn(async move { execute(&request, calls).await });
+        }
+
+        result.await.unwrap_or_else(|_| {
+            Err(Error::UnexpectedResponse(
+                "batched call was dropped before getting a result".to_owned(),
+            ))
+        })
+    }
+}
+
+/// Sends `calls` as one `execute` and hands every call its own result.
+async fn execute(request: &RequestBuilder, calls: Vec<Call>) {
+    if calls.is_empty() {
+        return;
+    }
+
+    let code = format!(
+        "return [{}];",
+        calls
+            .iter()
+            .map(|call| format!(
+                "API.{}({})",
+                call.method,
+                Value::Object(call.params.clone())
+            ))
+            .collect::<Vec<_>>()
+            .join(",")
+    );
+
+    let response = match request.execute(&code).await {
+        Ok(response) => response,
+        Err(err) => {
+            let err = Arc::new(err);
+            for call in calls {
+                let _ = call.reply.send(Err(Error::BatchFailed(Arc::clone(&err))));
+            }
+            return;
+        }
+    };
+
+    let results = response["response"].as_array().cloned().unwrap_or_default();
+    // Failed calls return `false`, their errors are listed in the same order
+    let mut errors = response["execute_errors"]
+        .as_array()
+        .cloned()
+        .unwrap_or_default()
+        .into_iter();
+
+    for (i, call) in calls.into_iter().enumerate() {
+        let result = results.get(i).cloned().unwrap_or(Value::Bool(false));
+        let failed = result == Value::Bool(false);
+        let reply = match failed.then(|| errors.next()).flatten() {
+            Some(err) => Err(Error::VkApi(VkError::from_vk_error_json(&err))),
+            None => Ok(json!({ "response": result })),
+        };
+
+        let _ = call.reply.send(reply);
+    }
+}
+
+/// Turns `a=1&b=2&` into `{"a": "1", "b": "2"}`.
+fn params(query: &str) -> Map<String, Value> {
+    query_params(query)
+        .map(|(key, value)| (key.to_owned(), Value::String(value.into_owned())))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::MockApi;
+    use futures_util::future::join_all;
+
+    fn batched(api: &Arc<MockApi>, window: Duration) -> RequestBuilder {
+        let mut request = RequestBuilder::builder("test")
+            .batch(window)
+            .without_rate_limit()
+            .build()
+            .unwrap();
+        request.set_mock(Arc::clone(api));
+        request
+    }
+
+    /// Number of calls in every `execute` sent so far.
+    fn batch_sizes(api: &MockApi) -> Vec<usize> {
+        api.calls()
+            .iter()
+            .map(|call| {
+                assert_eq!(call.method, "execute");
+                call.param("code").unwrap().matches("API.").count()
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn full_batch_is_sent_right_away() {
+        let api = Arc::new(MockApi::default());
+        // Only a full batch can be sent before the test times out
+        let request = batched(&api, Duration::from_secs(3600));
+
+        let calls = (0..MAX_CALLS).map(|_| request.call_method("users.get", b"user_ids=1&"));
+        tokio::time::timeout(Duration::from_secs(5), join_all(calls))
+            .await
+            .unwrap();
+
+        assert_eq!(batch_sizes(&api), [MAX_CALLS]);
+    }
+
+    #[tokio::test]
+    async fn call_over_the_limit_goes_into_next_batch() {
+        let api = Arc::new(MockApi::default());
+        api.respond_once("execute", json!({ "response": vec![1; MAX_CALLS] }));
+        api.respond_once("execute", json!({ "response": [2] }));
+        let request = batched(&api, Duration::from_millis(20));
+
+        let queries = (0..=MAX_CALLS)
+            .map(|i| format!("i={i}&"))
+            .collect::<Vec<_>>();
+        let results = join_all(
+            queries
+                .iter()
+                .map(|query| request.call_method("users.get", query.as_bytes())),
+        )
+        .await;
+
+        assert_eq!(batch_sizes(&api), [MAX_CALLS, 1]);
+        let code = api.calls()[0].param("code").unwrap().to_owned();
+        assert!(
+            code.starts_with(r#"return [API.users.get({"i":"0"}),"#),
+            "{code}"
+        );
+        assert!(code.ends_with(&format!(r#"API.users.get({{"i":"{}"}})];"#, MAX_CALLS - 1)));
+
+        let results = results
+            .into_iter()
+            .map(|result| result.unwrap()["response"].clone())
+            .collect::<Vec<_>>();
+        assert_eq!(results[..MAX_CALLS], vec![json!(1); MAX_CALLS]);
+        assert_eq!(results[MAX_CALLS], 2);
+    }
+
+    #[tokio::test]
+    async fn failed_calls_get_their_own_errors() {
+        let api = Arc::new(MockApi::default());
+        api.respond(
+            "execute",
+            json!({
+                "response": [false, { "id": 2 }, false, 0],
+                "execute_errors": [
+                    { "method": "messages.send", "error_code": 9, "error_msg": "Flood control" },
+                    { "method": "users.get", "error_code": 7, "error_msg": "Permission denied" },
+                ],
+            }),
+        );
+        let request = batched(&api, Duration::from_millis(20));
+
+        let results = join_all([
+            request.call_method("messages.send", b"peer_id=1&"),
+            request.call_method("users.get", b"user_ids=2&"),
+            request.call_method("users.get", b"user_ids=3&"),
+            request.call_method("messages.delete", b"message_ids=4&"),
+        ])
+        .await;
+
+        assert_eq!(batch_sizes(&api), [4]);
+        assert!(matches!(
+            &results[0],
+            Err(Error::VkApi(VkError::TooManySimilarActions(msg))) if msg == "Flood control"
+        ));
+        assert_eq!(
+            results[1].as_ref().unwrap(),
+            &json!({ "response": { "id": 2 } })
+        );
+        assert!(matches!(
+            &results[2],
+            Err(Error::VkApi(VkError::NoPermissionForAction(msg))) if msg == "Permission denied"
+        ));
+        // Only `false` is a failure, other falsy results are fine
+        assert_eq!(results[3].as_ref().unwrap(), &json!({ "response": 0 }));
+    }
+
+    #[tokio::test]
+    async fn failed_execute_fails_every_call() {
+        let api = Arc::new(MockApi::default());
+        api.respond(
+            "execute",
+            json!({ "error": { "error_code": 10, "error_msg": "Internal server error" } }),
+        );
+        let request = batched(&api, Duration::from_millis(20));
+
+        let results = join_all([
+            request.call_method("users.get", b"user_ids=1&"),
+            request.call_method("users.get", b"user_ids=2&"),
+        ])
+        .await;
+
+        for result in results {
+            assert!(matches!(result, Err(Error::BatchFailed(_))), "{result:?}");
+        }
+    }
+}
@@ -0,0 +1,31 @@
+use crate::Result;
+use futures_util::future::BoxFuture;
+use std::fmt;
+
+/// Solves captchas VK asks for with [`VkError::CaptchaRequired`](crate::VkError::CaptchaRequired),
+/// see [`RequestConfig::captcha_solver`](crate::RequestConfig::captcha_solver).
+///
+/// ```ignore
+/// struct AskAdmin;
+///
+/// impl CaptchaSolver for AskAdmin {
+///     fn solve<'a>(&'a self, captcha_img: &'a str) -> BoxFuture<'a, Result<String>> {
+///         Box::pin(async move {
+///             println!("Enter the text from {captcha_img}");
+///             let mut key = String::new();
+///             std::io::stdin().read_line(&mut key)?;
+///             Ok(key.trim().to_owned())
+///         })
+///     }
+/// }
+/// ```
+pub trait CaptchaSolver: Send + Sync + 'static {
+    /// Returns the text shown on the image at `captcha_img`.
+    fn solve<'a>(&'a self, captcha_img: &'a str) -> BoxFuture<'a, Result<String>>;
+}
+
+impl fmt::Debug for dyn CaptchaSolver {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("CaptchaSolver")
+    }
+}
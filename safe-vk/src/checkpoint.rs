@@ -0,0 +1,125 @@
+//! Persisting the long poll `ts` so the bot can resume where it stopped after a restart.
+//!
+//! [`Polling`](crate::start_polling::Polling) loads the last saved `ts` on startup and saves
+//! a new one once every handler of a batch is done, see
+//! [`Polling::checkpoint`](crate::start_polling::Polling::checkpoint). A batch is never
+//! saved before the ones received earlier, so a crash or a handler aborted on shutdown
+//! makes VK send the unfinished batches again, and updates are delivered at least once.
+//!
+//! ```ignore
+//! safe_vk::start_polling(&token, bot)
+//!     .checkpoint(FileCheckpoint::new("ts.txt"))
+//!     .await
+//!     .unwrap();
+//! ```
+use crate::{dispatch::Batch, Response};
+use futures_util::future::BoxFuture;
+use std::{
+    collections::VecDeque,
+    io::ErrorKind,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+/// Storage for the last long poll `ts`.
+///
+/// Implement it to keep the checkpoint in a database, Redis or anywhere else.
+pub trait CheckpointStore: Send + Sync + 'static {
+    /// Returns the last saved `ts`, `None` if nothing was saved yet.
+    fn load(&self) -> BoxFuture<'_, Response<Option<String>>>;
+
+    /// Replaces the saved `ts` with a new one.
+    fn save<'a>(&'a self, ts: &'a str) -> BoxFuture<'a, Response<()>>;
+}
+
+/// Batches of updates whose `ts` isn't saved yet, oldest first.
+#[derive(Debug, Default)]
+pub(crate) struct Unsaved(VecDeque<(String, Batch)>);
+
+impl Unsaved {
+    /// Starts tracking the batch received along with `ts`.
+    pub(crate) fn push(&mut self, ts: String) -> Batch {
+        let batch = Batch::default();
+        self.0.push_back((ts, batch.clone()));
+        batch
+    }
+
+    /// Forgets the finished batches up to the first unfinished one, returning the `ts` of
+    /// the last of them.
+    pub(crate) fn take_done(&mut self) -> Option<String> {
+        let mut ts = None;
+        while self.0.front().is_some_and(|(_, batch)| batch.is_done()) {
+            ts = self.0.pop_front().map(|(ts, _)| ts);
+        }
+        ts
+    }
+}
+
+/// Keeps the `ts` in memory, so it survives restarting the polling future but not the process.
+///
+/// Clones share the same value.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryCheckpoint(Arc<Mutex<Option<String>>>);
+
+impl MemoryCheckpoint {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the currently saved `ts`.
+    pub fn get(&self) -> Option<String> {
+        self.0.lock().unwrap().clone()
+    }
+}
+
+impl CheckpointStore for MemoryCheckpoint {
+    fn load(&self) -> BoxFuture<'_, Response<Option<String>>> {
+        Box::pin(std::future::ready(Ok(self.get())))
+    }
+
+    fn save<'a>(&'a self, ts: &'a str) -> BoxFuture<'a, Response<()>> {
+        *self.0.lock().unwrap() = Some(ts.to_owned());
+        Box::pin(std::future::ready(Ok(())))
+    }
+}
+
+/// Keeps the `ts` in a plain text file.
+///
+/// The file is written to a temporary file first and then renamed, so a crash in the
+/// middle of saving never leaves a broken checkpoint behind.
+#[derive(Debug, Clone)]
+pub struct FileCheckpoint {
+    path: PathBuf,
+}
+
+impl FileCheckpoint {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+}
+
+impl CheckpointStore for FileCheckpoint {
+    fn load(&self) -> BoxFuture<'_, Response<Option<String>>> {
+        Box::pin(async move {
+            match tokio::fs::read_to_string(&self.path).await {
+                Ok(ts) => {
+                    let ts = ts.trim();
+                    Ok((!ts.is_empty()).then(|| ts.to_owned()))
+                }
+                Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
+                Err(err) => Err(err.into()),
+            }
+        })
+    }
+
+    fn save<'a>(&'a self, ts: &'a str) -> BoxFuture<'a, Response<()>> {
+        Box::pin(async move {
+            let mut tmp = self.path.clone().into_os_string();
+            tmp.push(".tmp");
+
+            tokio::fs::write(&tmp, ts).await?;
+            tokio::fs::rename(&tmp, &self.path).await?;
+            Ok(())
+        })
+    }
+}
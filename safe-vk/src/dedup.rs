@@ -0,0 +1,93 @@
+//! Skipping updates that VK has already delivered once.
+//!
+//! VK may send the same event more than once: the Callback API retries events it didn't get
+//! `ok` for in time, and long poll replays history after `failed: 1`. Every event carries a
+//! unique `event_id`, so once deduplication is enabled with `.dedup(..)` on
+//! [`start_polling`](crate::start_polling()) or `serve_callback`, an update whose `event_id`
+//! was already seen never reaches a handler. An update the bot failed to take is delivered
+//! again and isn't skipped then.
+//!
+//! ```ignore
+//! safe_vk::serve_callback(&token, ([0, 0, 0, 0], 8080), "a1b2c3d4", bot)
+//!     .dedup(MemoryDedup::new(10_000, Duration::from_secs(600)))
+//!     .await
+//!     .unwrap();
+//! ```
+use crate::Response;
+use futures_util::future::BoxFuture;
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// Storage for the `event_id`s of the updates that were already dispatched.
+///
+/// Implement it to share the seen events between several instances of the bot,
+/// e.g. with Redis `SET NX EX`.
+pub trait DedupStore: Send + Sync + 'static {
+    /// Remembers `event_id`, returns `false` if it was already remembered before.
+    fn insert<'a>(&'a self, event_id: &'a str) -> BoxFuture<'a, Response<bool>>;
+}
+
+/// Remembers up to `capacity` of the latest `event_id`s in memory, each for at most `ttl`.
+#[derive(Debug)]
+pub struct MemoryDedup {
+    capacity: usize,
+    ttl: Duration,
+    seen: Mutex<Seen>,
+}
+
+#[derive(Debug, Default)]
+struct Seen {
+    ids: HashMap<String, Instant>,
+    order: VecDeque<(String, Instant)>,
+}
+
+impl MemoryDedup {
+    pub fn new(capacity: usize, ttl: Duration) -> Self {
+        Self {
+            capacity,
+            ttl,
+            seen: Mutex::default(),
+        }
+    }
+}
+
+impl Default for MemoryDedup {
+    /// Remembers up to 10 000 events for 10 minutes.
+    fn default() -> Self {
+        Self::new(10_000, Duration::from_secs(600))
+    }
+}
+
+impl DedupStore for MemoryDedup {
+    fn insert<'a>(&'a self, event_id: &'a str) -> BoxFuture<'a, Response<bool>> {
+        let now = Instant::now();
+        let mut seen = self.seen.lock().unwrap();
+        let Seen { ids, order } = &mut *seen;
+
+        // Forget the oldest events, both outdated ones and the ones over capacity
+        while let Some((id, at)) = order.front() {
+            if order.len() < self.capacity && now.duration_since(*at) < self.ttl {
+                break;
+            }
+
+            if ids.get(id) == Some(at) {
+                ids.remove(id);
+            }
+            order.pop_front();
+        }
+
+        let is_new = match ids.get(event_id) {
+            Some(at) if now.duration_since(*at) < self.ttl => false,
+            _ => {
+                ids.insert(event_id.to_owned(), now);
+                order.push_back((event_id.to_owned(), now));
+                true
+            }
+        };
+
+        Box::pin(std::future::ready(Ok(is_new)))
+    }
+}
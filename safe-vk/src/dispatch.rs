@@ -0,0 +1,510 @@
+use crate::{
+    dedup::DedupStore,
+    extract::{find_id, Ctx, Update},
+    service::{ConcurrencyLimit, Service},
+    Error, RequestBuilder, Response,
+};
+use futures_util::{future::BoxFuture, FutureExt};
+use std::{
+    collections::HashMap,
+    future::{poll_fn, Future},
+    panic::AssertUnwindSafe,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+use tokio::{
+    sync::mpsc::{self, UnboundedReceiver, UnboundedSender},
+    task::JoinSet,
+};
+
+/// Type-erased hook called whenever a handler returns an error.
+pub(crate) type ErrorHandler =
+    Arc<dyn Fn(Error, Ctx<Update>) -> BoxFuture<'static, ()> + Send + Sync>;
+
+pub(crate) fn error_handler<F, Fut>(f: F) -> ErrorHandler
+where
+    F: Fn(Error, Ctx<Update>) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    Arc::new(move |err, ctx| Box::pin(f(err, ctx)))
+}
+
+/// Used when no `on_error` hook is set, simply logs the error.
+fn log_error(err: Error, ctx: Ctx<Update>) -> BoxFuture<'static, ()> {
+    error!(
+        "Handler failed on `{}` event {}: {err}",
+        ctx.update_type, ctx.event_id
+    );
+    Box::pin(std::future::ready(()))
+}
+
+/// Defines in which order the handlers of incoming updates run.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum DispatchMode {
+    /// Every update is handled in its own task as soon as it arrives,
+    /// so two updates from the same chat may be handled out of order.
+    #[default]
+    Concurrent,
+    /// Updates from the same conversation (`peer_id`) are handled one after another,
+    /// while different conversations are still handled in parallel.
+    PerPeer,
+    /// Same as [`DispatchMode::PerPeer`], but the order is only kept for updates
+    /// from the same sender (`from_id`) inside a conversation.
+    PerSender,
+}
+
+impl DispatchMode {
+    /// Finds out which queue the `update` belongs to, `None` means it can run right away.
+    fn queue_key(self, update: &Update) -> Option<QueueKey> {
+        let peer_id = || find_id(&update.object, "peer_id");
+        let from_id =
+            || find_id(&update.object, "from_id").or_else(|| find_id(&update.object, "user_id"));
+
+        match self {
+            DispatchMode::Concurrent => None,
+            DispatchMode::PerPeer => Some((peer_id()?, None)),
+            DispatchMode::PerSender => Some((peer_id()?, from_id())),
+        }
+    }
+}
+
+/// `peer_id` and optionally `from_id` of the updates that must be handled in order.
+type QueueKey = (i64, Option<i64>);
+
+type Job = BoxFuture<'static, ()>;
+
+type Queues = Arc<Mutex<HashMap<QueueKey, UnboundedSender<Job>>>>;
+
+/// Settings shared by every event source, see [`dispatch_options`].
+#[derive(Default)]
+pub(crate) struct DispatchOptions {
+    pub(crate) on_error: Option<ErrorHandler>,
+    pub(crate) max_concurrent_handlers: Option<usize>,
+    pub(crate) mode: DispatchMode,
+    pub(crate) dedup: Option<Arc<dyn DedupStore>>,
+}
+
+/// Implements builder methods for [`DispatchOptions`] on an event source that keeps
+/// them in its `options` field.
+macro_rules! dispatch_options {
+    ($source:ident) => {
+        impl<M, S> $source<M, S> {
+            /// Sets a hook that is called whenever a handler returns an error.
+            ///
+            /// The hook receives the error and a [`Ctx`](crate::extract::Ctx) holding the
+            /// update that caused it, so it can log the update, notify admins or reply to
+            /// the user. By default the error is just logged.
+            ///
+            /// ```ignore
+            /// safe_vk::start_polling(&token, bot)
+            ///     .on_error(|err, ctx: Ctx<Update>| async move {
+            ///         eprintln!("{err}");
+            ///         if let Ok(messages) = ctx.messages() {
+            ///             let _ = messages.send().random_id(0).message("Something went wrong").await;
+            ///         }
+            ///     })
+            ///     .await
+            ///     .unwrap();
+            /// ```
+            pub fn on_error<F, Fut>(mut self, f: F) -> Self
+            where
+                F: Fn($crate::Error, $crate::extract::Ctx<$crate::extract::Update>) -> Fut
+                    + Send
+                    + Sync
+                    + 'static,
+                Fut: std::future::Future<Output = ()> + Send + 'static,
+            {
+                self.options.on_error = Some($crate::dispatch::error_handler(f));
+                self
+            }
+
+            /// Limits how many handlers can run at the same time.
+            ///
+            /// Once `max` handlers are running, no new updates are read until one of
+            /// them finishes, so a flood of events doesn't turn into a flood of tasks.
+            ///
+            /// # Panics
+            ///
+            /// If `max` is zero, since no handler could ever run.
+            pub fn max_concurrent_handlers(mut self, max: usize) -> Self {
+                assert!(
+                    max > 0,
+                    "`max_concurrent_handlers` must be greater than zero"
+                );
+                self.options.max_concurrent_handlers = Some(max);
+                self
+            }
+
+            /// Sets in which order the updates are handled, see [`DispatchMode`](crate::DispatchMode).
+            ///
+            /// ```ignore
+            /// safe_vk::start_polling(&token, bot)
+            ///     .dispatch_mode(DispatchMode::PerPeer)
+            ///     .await
+            ///     .unwrap();
+            /// ```
+            pub fn dispatch_mode(mut self, mode: $crate::DispatchMode) -> Self {
+                self.options.mode = mode;
+                self
+            }
+
+            /// Skips updates whose `event_id` is already in `store`, so VK redelivering
+            /// an event doesn't make the handlers run twice, see [`dedup`](crate::dedup).
+            pub fn dedup(mut self, store: impl $crate::dedup::DedupStore) -> Self {
+                self.options.dedup = Some(std::sync::Arc::new(store));
+                self
+            }
+        }
+    };
+}
+
+pub(crate) use dispatch_options;
+
+/// Hands incoming updates over to the router, no matter where they came from.
+///
+/// Both [`start_polling`](crate::start_polling) and [`serve_callback`](crate::serve_callback)
+/// feed their events through a [`Dispatcher`], so every event source behaves the same way.
+pub(crate) struct Dispatcher<M> {
+    safevk: ConcurrencyLimit<M>,
+    request: Arc<RequestBuilder>,
+    tasks: JoinSet<()>,
+    on_error: ErrorHandler,
+    mode: DispatchMode,
+    queues: Queues,
+    dedup: Option<Arc<dyn DedupStore>>,
+}
+
+impl<M> Dispatcher<M>
+where
+    M: Service<Update> + Send + Clone + 'static,
+    <M as Service<Update>>::Future: Send + 'static,
+{
+    pub(crate) fn new(safevk: M, request: Arc<RequestBuilder>, options: DispatchOptions) -> Self {
+        let DispatchOptions {
+            on_error,
+            max_concurrent_handlers,
+            mode,
+            dedup,
+        } = options;
+
+        let safevk = match max_concurrent_handlers {
+            Some(max) => ConcurrencyLimit::new(safevk, max),
+            None => ConcurrencyLimit::unlimited(safevk),
+        };
+
+        Self {
+            safevk,
+            request,
+            tasks: JoinSet::new(),
+            on_error: on_error.unwrap_or_else(|| Arc::new(log_error)),
+            mode,
+            queues: Default::default(),
+            dedup,
+        }
+    }
+
+    /// Waits until the router is ready and spawns a task handling the `update`.
+    pub(crate) async fn dispatch(&mut self, update: Update) -> Response<()> {
+        self.dispatch_in(update, None).await
+    }
+
+    /// Same as [`dispatch`](Self::dispatch), the handler counts as unfinished in `batch`
+    /// until it is done.
+    pub(crate) async fn dispatch_in(
+        &mut self,
+        update: Update,
+        batch: Option<&Batch>,
+    ) -> Response<()> {
+        if let Some(metrics) = self.request.metrics() {
+            metrics.update_received(&update.update_type);
+        }
+
+        poll_fn(|cx| self.safevk.poll_ready(cx)).await?;
+
+        // Only an accepted event is remembered, one that failed above is handled once it's
+        // delivered again. A skipped one leaves the router ready for the next event
+        if let Some(dedup) = &self.dedup {
+            match dedup.insert(&update.event_id).await {
+                Ok(true) => {}
+                Ok(false) => return Ok(()),
+                // Better to handle an event twice than to lose it
+                Err(err) => warn!("Failed to check event {}: {err}", update.event_id),
+            }
+        }
+
+        // Forget about the tasks that are already done, so the set doesn't grow forever
+        while self.tasks.try_join_next().is_some() {}
+
+        let key = self.mode.queue_key(&update);
+        let ctx = Ctx::new(Arc::clone(&self.request), update.clone());
+        #[cfg(feature = "tracing")]
+        let span = tracing::info_span!(
+            "update",
+            event_type = %update.update_type,
+            event_id = %update.event_id,
+            peer_id = tracing::field::Empty,
+            route = tracing::field::Empty,
+        );
+        #[cfg(feature = "tracing")]
+        if let Some(peer_id) = find_id(&update.object, "peer_id") {
+            span.record("peer_id", peer_id);
+        }
+        // The router records the matched route into the current span
+        #[cfg(feature = "tracing")]
+        let future = span.in_scope(|| self.safevk.call(update, Arc::clone(&self.request)));
+        #[cfg(not(feature = "tracing"))]
+        let future = self.safevk.call(update, Arc::clone(&self.request));
+        let on_error = Arc::clone(&self.on_error);
+        let unfinished = batch.map(Batch::start);
+
+        let job = async move {
+            // A panicking handler is as done with its update as it will ever be, so its batch
+            // can still be saved, and the queue it runs in goes on with the next update
+            let handled = AssertUnwindSafe(future).catch_unwind().await;
+            match handled.map(Result::err) {
+                Ok(None) => {}
+                Ok(Some(err)) => on_error(err, ctx).await,
+                Err(_) => error!(
+                    "Handler panicked on `{}` event {}",
+                    ctx.update_type, ctx.event_id
+                ),
+            }
+            if let Some(unfinished) = unfinished {
+                unfinished.finish();
+            }
+        };
+        #[cfg(feature = "tracing")]
+        let job = tracing::Instrument::instrument(job, span);
+        let job: Job = Box::pin(job);
+
+        match key {
+            Some(key) => self.enqueue(key, job),
+            None => {
+                self.tasks.spawn(job);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Puts the `job` at the end of the queue for `key`, starting a worker for it if needed.
+    fn enqueue(&mut self, key: QueueKey, job: Job) {
+        let mut queues = self.queues.lock().unwrap();
+
+        // The worker removes itself from `queues` under the same lock once its queue is
+        // empty, so a sender found here always belongs to a worker that will run the job
+        let job = match queues.get(&key) {
+            Some(sender) => match sender.send(job) {
+                Ok(()) => return,
+                Err(err) => err.0,
+            },
+            None => job,
+        };
+
+        let (sender, receiver) = mpsc::unbounded_channel();
+        sender.send(job).expect("receiver is alive");
+        queues.insert(key, sender);
+        self.tasks
+            .spawn(run_queue(key, receiver, Arc::clone(&self.queues)));
+    }
+
+    /// Waits up to `timeout` for the running handlers, aborting the ones that didn't make it.
+    pub(crate) async fn shutdown(&mut self, timeout: Duration) {
+        let drain = async { while self.tasks.join_next().await.is_some() {} };
+
+        if tokio::time::timeout(timeout, drain).await.is_err() {
+            warn!(
+                "{} handler(s) didn't finish in {timeout:?} and were aborted",
+                self.tasks.len()
+            );
+            self.tasks.shutdown().await;
+        }
+    }
+}
+
+/// Counts the handlers of a batch of updates that haven't finished yet.
+///
+/// A handler aborted on shutdown never finishes, so its batch stays unfinished. One that
+/// panicked counts as finished.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Batch(Arc<AtomicUsize>);
+
+impl Batch {
+    fn start(&self) -> Self {
+        self.0.fetch_add(1, Ordering::AcqRel);
+        self.clone()
+    }
+
+    fn finish(self) {
+        self.0.fetch_sub(1, Ordering::AcqRel);
+    }
+
+    pub(crate) fn is_done(&self) -> bool {
+        self.0.load(Ordering::Acquire) == 0
+    }
+}
+
+/// Runs the jobs of a single queue one by one, exits once the queue is empty.
+async fn run_queue(key: QueueKey, mut receiver: UnboundedReceiver<Job>, queues: Queues) {
+    loop {
+        let job = match receiver.try_recv() {
+            Ok(job) => job,
+            Err(_) => {
+                let mut queues = queues.lock().unwrap();
+                // Checking again under the lock, nothing can be pushed in between
+                match receiver.try_recv() {
+                    Ok(job) => job,
+                    Err(_) => {
+                        queues.remove(&key);
+                        return;
+                    }
+                }
+            }
+        };
+
+        job.await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{dedup::MemoryDedup, test::TestBot};
+    use std::task::{Context, Poll};
+    use tokio::sync::Notify;
+
+    /// Not ready the first time it's polled, counts the updates it handled.
+    #[derive(Clone, Default)]
+    struct Flaky {
+        polled: Arc<AtomicUsize>,
+        handled: Arc<AtomicUsize>,
+    }
+
+    impl Service<Update> for Flaky {
+        type Response = ();
+        type Future = std::future::Ready<Response<()>>;
+
+        fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Response<()>> {
+            match self.polled.fetch_add(1, Ordering::AcqRel) {
+                0 => Poll::Ready(Err(Error::Continue)),
+                _ => Poll::Ready(Ok(())),
+            }
+        }
+
+        fn call(&mut self, _update: Update, _request: Arc<RequestBuilder>) -> Self::Future {
+            self.handled.fetch_add(1, Ordering::AcqRel);
+            std::future::ready(Ok(()))
+        }
+    }
+
+    /// Reports `(peer_id, text)` of every message it starts and finishes, a `slow` one waits
+    /// for `gate` in between.
+    #[derive(Clone)]
+    struct Gated {
+        gate: Arc<Notify>,
+        log: UnboundedSender<Event>,
+    }
+
+    impl Service<Update> for Gated {
+        type Response = ();
+        type Future = BoxFuture<'static, Response<()>>;
+
+        fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Response<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, update: Update, _request: Arc<RequestBuilder>) -> Self::Future {
+            let Gated { gate, log } = self.clone();
+            let message = &update.object["message"];
+            let peer_id = message["peer_id"].as_i64().unwrap();
+            let text = message["text"].as_str().unwrap().to_owned();
+            Box::pin(async move {
+                log.send(("start", peer_id, text.clone())).unwrap();
+                if text == "slow" {
+                    gate.notified().await;
+                }
+                log.send(("end", peer_id, text)).unwrap();
+                Ok(())
+            })
+        }
+    }
+
+    type Event = (&'static str, i64, String);
+
+    async fn next(events: &mut UnboundedReceiver<Event>) -> Event {
+        tokio::time::timeout(Duration::from_secs(1), events.recv())
+            .await
+            .expect("handler didn't report in time")
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn per_peer_keeps_order_inside_a_peer_only() {
+        let (log, mut events) = mpsc::unbounded_channel();
+        let gate = Arc::new(Notify::new());
+        let service = Gated {
+            gate: Arc::clone(&gate),
+            log,
+        };
+        let options = DispatchOptions {
+            mode: DispatchMode::PerPeer,
+            ..Default::default()
+        };
+        let request = Arc::new(RequestBuilder::new("test"));
+        let mut dispatcher = Dispatcher::new(service, request, options);
+
+        for (peer_id, text) in [(1, "slow"), (1, "fast"), (2, "fast")] {
+            let update = TestBot::message(text).peer(peer_id).into();
+            dispatcher.dispatch(update).await.unwrap();
+        }
+
+        let started = |peer_id, text: &str| ("start", peer_id, text.to_owned());
+        let ended = |peer_id, text: &str| ("end", peer_id, text.to_owned());
+
+        // The slow handler of peer 1 doesn't hold peer 2 back
+        let mut seen = vec![
+            next(&mut events).await,
+            next(&mut events).await,
+            next(&mut events).await,
+        ];
+        seen.sort();
+        assert_eq!(
+            seen,
+            [ended(2, "fast"), started(1, "slow"), started(2, "fast")]
+        );
+
+        // The second message of peer 1 waits for the first one
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(events.try_recv().is_err());
+
+        gate.notify_one();
+        assert_eq!(next(&mut events).await, ended(1, "slow"));
+        assert_eq!(next(&mut events).await, started(1, "fast"));
+        assert_eq!(next(&mut events).await, ended(1, "fast"));
+
+        dispatcher.shutdown(Duration::from_secs(1)).await;
+    }
+
+    #[tokio::test]
+    async fn failed_dispatch_is_not_remembered() {
+        let service = Flaky::default();
+        let options = DispatchOptions {
+            dedup: Some(Arc::new(MemoryDedup::default())),
+            ..Default::default()
+        };
+        let request = Arc::new(RequestBuilder::new("test"));
+        let mut dispatcher = Dispatcher::new(service.clone(), request, options);
+        let update = TestBot::event("message_new", serde_json::json!({}));
+
+        assert!(dispatcher.dispatch(update.clone()).await.is_err());
+        // Delivered again after the failure, then once more
+        dispatcher.dispatch(update.clone()).await.unwrap();
+        dispatcher.dispatch(update).await.unwrap();
+        dispatcher.shutdown(Duration::from_secs(1)).await;
+
+        assert_eq!(service.handled.load(Ordering::Acquire), 1);
+    }
+}
@@ -0,0 +1,381 @@
+use super::{find_peer_id, FromUpdate, Parts, RequestBuilder, Update};
+use crate::{
+    api::{AbstractionMessages, MethodBuilder},
+    Error,
+};
+use std::{fmt, sync::Arc};
+
+/// Arguments written after the trigger of the command, parsed into `T`.
+///
+/// `T` is a tuple of [`FromArg`] types or a struct deriving [`FromArgs`]. Arguments are
+/// separated by spaces, an argument with spaces can be put in double quotes, and `Option`
+/// arguments at the end can be left out:
+///
+/// ```ignore
+/// // `/cfg 7.5`
+/// async fn cfg(Args((cfg,)): Args<(f32,)>) -> Result<()> { .. }
+///
+/// // `/ban 123 "for spam"` or `/ban 123`
+/// #[derive(FromArgs)]
+/// struct Ban {
+///     user: i64,
+///     reason: Option<String>,
+/// }
+///
+/// async fn ban(Args(ban): Args<Ban>) -> Result<()> { .. }
+/// ```
+///
+/// If the arguments can't be parsed, the handler isn't called and the sender gets a reply
+/// with the usage of the command instead, e.g. ``Usage: /ban <user> [reason]``.
+///
+/// Commands taking arguments are added with [`Filter::Prefix`](crate::Filter::Prefix), which
+/// matches messages starting with the trigger. [`Filter::Strict`](crate::Filter::Strict) and
+/// [`Filter::Flexible`](crate::Filter::Flexible) match the whole message, so a message with
+/// arguments never reaches such a command, and [`Filter::Sensitive`](crate::Filter::Sensitive)
+/// finds the trigger anywhere in the message, taking the text after it as arguments.
+/// With [`command_regex`](crate::SafeVk::command_regex) the arguments are the text after the
+/// match.
+#[derive(Debug, Clone)]
+pub struct Args<T>(pub T);
+
+impl<T> std::ops::Deref for Args<T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl<T, S> FromUpdate<S> for Args<T>
+where
+    T: FromArgs + Send,
+    S: Send + Sync,
+{
+    type Rejection = ArgsRejection;
+
+    async fn from_update(
+        update: Update,
+        parts: &Parts,
+        _state: &S,
+        request: Arc<RequestBuilder>,
+    ) -> Result<Self, Self::Rejection> {
+        let rest = parts.route.rest.as_deref().unwrap_or_default();
+        let parsed = split(rest).and_then(|args| T::from_args(&args));
+
+        match parsed {
+            Ok(args) => Ok(Self(args)),
+            Err(error) => {
+                let command = parts.route.command.clone().unwrap_or_default();
+                let usage = format!("{command} {}", T::usage()).trim().to_owned();
+                let rejection = ArgsRejection { error, usage };
+
+                if let Ok(peer_id) = find_peer_id(&update.object) {
+                    let reply = <MethodBuilder as AbstractionMessages>::new(request, Some(peer_id))
+                        .send()
+                        .random_id(0)
+                        .message(&rejection.to_string())
+                        .await;
+                    if let Err(err) = reply {
+                        warn!("Failed to send the usage of `{command}`: {err}");
+                    }
+                }
+
+                Err(rejection)
+            }
+        }
+    }
+}
+
+/// Splits `text` by whitespace, keeping text in double quotes together. `\"` and `\\` put
+/// a quote or a backslash into a quoted argument.
+fn split(text: &str) -> Result<Vec<String>, ArgsError> {
+    let mut args = Vec::new();
+    let mut chars = text.chars().peekable();
+
+    loop {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        let Some(first) = chars.next() else {
+            return Ok(args);
+        };
+
+        let mut arg = String::new();
+        if first == '"' {
+            loop {
+                match chars.next() {
+                    Some('"') => break,
+                    Some('\\') => match chars.next_if(|c| matches!(c, '"' | '\\')) {
+                        Some(escaped) => arg.push(escaped),
+                        None => arg.push('\\'),
+                    },
+                    Some(c) => arg.push(c),
+                    None => return Err(ArgsError::UnclosedQuote),
+                }
+            }
+        } else {
+            arg.push(first);
+            while let Some(c) = chars.next_if(|c| !c.is_whitespace()) {
+                arg.push(c);
+            }
+        }
+        args.push(arg);
+    }
+}
+
+/// Arguments of a command, extracted with [`Args`].
+///
+/// Implemented for tuples of [`FromArg`] types and derived for structs with
+/// [`#[derive(FromArgs)]`](macro@crate::FromArgs), which uses the field names in the usage.
+pub trait FromArgs: Sized {
+    fn from_args(args: &[String]) -> Result<Self, ArgsError>;
+
+    /// Arguments as shown in the usage message, e.g. `<user> [reason]`.
+    fn usage() -> String;
+}
+
+/// A single argument of a command.
+pub trait FromArg: Sized {
+    /// Whether the argument can be left out, only the last arguments can be.
+    const OPTIONAL: bool = false;
+
+    fn from_arg(arg: &str) -> Result<Self, String>;
+
+    /// Value of the argument when it's left out.
+    fn missing() -> Option<Self> {
+        None
+    }
+
+    /// Name of the argument in the usage message when it has no name of its own.
+    fn placeholder() -> &'static str {
+        let name = std::any::type_name::<Self>();
+        let name = name.split('<').next().unwrap_or(name);
+        name.rsplit("::").next().unwrap_or(name)
+    }
+
+    /// The `index`th argument, `name` is used in errors.
+    fn nth(args: &[String], index: usize, name: &str) -> Result<Self, ArgsError> {
+        match args.get(index) {
+            Some(arg) => Self::from_arg(arg).map_err(|reason| ArgsError::Invalid {
+                name: name.to_owned(),
+                reason,
+            }),
+            None => Self::missing().ok_or_else(|| ArgsError::Missing {
+                name: name.to_owned(),
+            }),
+        }
+    }
+
+    /// `name` in the usage message, `<name>` or `[name]` if it's optional.
+    fn usage(name: &str) -> String {
+        if Self::OPTIONAL {
+            format!("[{name}]")
+        } else {
+            format!("<{name}>")
+        }
+    }
+}
+
+macro_rules! impl_from_arg {
+    ($($ty:ty),*) => {
+        $(
+            impl FromArg for $ty {
+                fn from_arg(arg: &str) -> Result<Self, String> {
+                    arg.parse().map_err(|err| format!("{err}"))
+                }
+            }
+        )*
+    };
+}
+
+impl_from_arg!(
+    i8, i16, i32, i64, i128, isize, u8, u16, u32, u64, u128, usize, f32, f64, bool, char
+);
+
+impl FromArg for String {
+    fn from_arg(arg: &str) -> Result<Self, String> {
+        Ok(arg.to_owned())
+    }
+
+    fn placeholder() -> &'static str {
+        "text"
+    }
+}
+
+impl<T: FromArg> FromArg for Option<T> {
+    const OPTIONAL: bool = true;
+
+    fn from_arg(arg: &str) -> Result<Self, String> {
+        T::from_arg(arg).map(Some)
+    }
+
+    fn missing() -> Option<Self> {
+        Some(None)
+    }
+
+    fn placeholder() -> &'static str {
+        T::placeholder()
+    }
+}
+
+macro_rules! impl_from_args {
+    (
+        [$($ty:ident),*]
+    ) => {
+        impl<$($ty,)*> FromArgs for ($($ty,)*)
+        where
+            $( $ty: FromArg, )*
+        {
+            #[allow(unused_assignments)]
+            fn from_args(args: &[String]) -> Result<Self, ArgsError> {
+                let expected = [$(stringify!($ty),)*].len();
+                if args.len() > expected {
+                    return Err(ArgsError::TooMany { expected });
+                }
+
+                let mut index = 0;
+                Ok(($(
+                    {
+                        let arg = $ty::nth(args, index, $ty::placeholder())?;
+                        index += 1;
+                        arg
+                    },
+                )*))
+            }
+
+            fn usage() -> String {
+                [$($ty::usage($ty::placeholder()),)*].join(" ")
+            }
+        }
+    };
+}
+all_the_tuples!(impl_from_args);
+
+/// Why the arguments of a command couldn't be parsed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ArgsError {
+    /// A required argument was left out.
+    Missing { name: String },
+    /// An argument couldn't be parsed.
+    Invalid { name: String, reason: String },
+    /// More arguments than the command takes were given.
+    TooMany { expected: usize },
+    /// A quoted argument has no closing quote.
+    UnclosedQuote,
+}
+
+impl fmt::Display for ArgsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ArgsError::Missing { name } => write!(f, "Missing argument `{name}`"),
+            ArgsError::Invalid { name, reason } => {
+                write!(f, "Invalid argument `{name}`: {reason}")
+            }
+            ArgsError::TooMany { expected } => {
+                write!(f, "Too many arguments, expected at most {expected}")
+            }
+            ArgsError::UnclosedQuote => write!(f, "Unclosed quote"),
+        }
+    }
+}
+
+impl std::error::Error for ArgsError {}
+
+/// Rejection of [`Args`], also sent as a reply to the message that had the wrong arguments.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArgsRejection {
+    pub error: ArgsError,
+    /// The command followed by its arguments, e.g. `/ban <user> [reason]`.
+    pub usage: String,
+}
+
+impl fmt::Display for ArgsRejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}\nUsage: {}", self.error, self.usage)
+    }
+}
+
+impl std::error::Error for ArgsRejection {}
+
+impl From<ArgsRejection> for Error {
+    fn from(rejection: ArgsRejection) -> Self {
+        Error::Rejection(rejection.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn split_keeps_quoted_text_together() {
+        assert_eq!(
+            split("  123   \"for spam\" x ").unwrap(),
+            args(&["123", "for spam", "x"])
+        );
+        assert_eq!(split("\"\"").unwrap(), args(&[""]));
+        assert_eq!(split("").unwrap(), args(&[]));
+    }
+
+    #[test]
+    fn split_unescapes_quotes_and_backslashes() {
+        assert_eq!(
+            split(r#""say \"hi\"" "a\\b" "c\d" e\"f"#).unwrap(),
+            args(&[r#"say "hi""#, r"a\b", r"c\d", r#"e\"f"#])
+        );
+    }
+
+    #[test]
+    fn split_rejects_an_unclosed_quote() {
+        assert_eq!(split(r#"123 "for spam"#), Err(ArgsError::UnclosedQuote));
+        assert_eq!(split(r#""ends with \""#), Err(ArgsError::UnclosedQuote));
+    }
+
+    #[test]
+    fn too_many_args() {
+        assert_eq!(
+            <(i64, Option<String>)>::from_args(&args(&["1", "2", "3"])),
+            Err(ArgsError::TooMany { expected: 2 })
+        );
+    }
+
+    #[test]
+    fn missing_args() {
+        assert_eq!(
+            <(i64, String)>::from_args(&args(&["1"])),
+            Err(ArgsError::Missing {
+                name: "text".to_owned()
+            })
+        );
+    }
+
+    #[test]
+    fn invalid_args() {
+        assert_eq!(
+            <(u8,)>::from_args(&args(&["300"])),
+            Err(ArgsError::Invalid {
+                name: "u8".to_owned(),
+                reason: "number too large to fit in target type".to_owned()
+            })
+        );
+    }
+
+    #[test]
+    fn trailing_option_can_be_left_out() {
+        assert_eq!(
+            <(i64, Option<String>)>::from_args(&args(&["1"])),
+            Ok((1, None))
+        );
+        assert_eq!(
+            <(i64, Option<String>)>::from_args(&args(&["1", "spam"])),
+            Ok((1, Some("spam".to_owned())))
+        );
+    }
+
+    #[test]
+    fn tuple_usage() {
+        assert_eq!(<(i64, Option<String>)>::usage(), "<i64> [text]");
+    }
+}
@@ -0,0 +1,73 @@
+use super::{FromUpdate, Parts, RequestBuilder, Update};
+use crate::Error;
+use serde::de::DeserializeOwned;
+use std::{fmt, sync::Arc};
+
+/// Named groups of the [`command_regex`](crate::SafeVk::command_regex) pattern that matched
+/// the message, deserialized into `T`.
+///
+/// Groups are strings, numbers and other primitives are parsed from them. A group that didn't
+/// take part in the match is missing, so it should be an `Option` field.
+///
+/// ```ignore
+/// #[derive(Deserialize)]
+/// struct Resize {
+///     width: u32,
+///     height: Option<u32>,
+/// }
+///
+/// // `^/resize (?P<width>\d+)(x(?P<height>\d+))?$`
+/// async fn resize(Captures(size): Captures<Resize>) -> Result<()> { .. }
+/// ```
+#[derive(Debug, Clone)]
+pub struct Captures<T>(pub T);
+
+impl<T> std::ops::Deref for Captures<T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl<T, S> FromUpdate<S> for Captures<T>
+where
+    T: DeserializeOwned,
+    S: Send + Sync,
+{
+    type Rejection = CapturesRejection;
+
+    async fn from_update(
+        _update: Update,
+        parts: &Parts,
+        _state: &S,
+        _request: Arc<RequestBuilder>,
+    ) -> Result<Self, Self::Rejection> {
+        // Going through the urlencoded format to get primitives parsed from strings
+        let encoded = serde_urlencoded::to_string(&parts.route.captures)
+            .map_err(|err| CapturesRejection(err.to_string()))?;
+        let captures = serde_urlencoded::from_str(&encoded)
+            .map_err(|err| CapturesRejection(err.to_string()))?;
+
+        Ok(Self(captures))
+    }
+}
+
+/// Rejection of [`Captures`], returned when the groups can't be deserialized into the type,
+/// e.g. because a group is missing or a number is too big.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CapturesRejection(String);
+
+impl fmt::Display for CapturesRejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Failed to deserialize regex captures: {}", self.0)
+    }
+}
+
+impl std::error::Error for CapturesRejection {}
+
+impl From<CapturesRejection> for Error {
+    fn from(rejection: CapturesRejection) -> Self {
+        Error::Rejection(rejection.to_string())
+    }
+}
@@ -1,7 +1,7 @@
-use super::{FromUpdate, RequestBuilder, Update};
+use super::{FromUpdate, JsonRejection, Parts, RequestBuilder, Update};
 use crate::{responses::Message, Error};
 use serde_json::Value;
-use std::sync::Arc;
+use std::{convert::Infallible, sync::Arc};
 
 #[derive(Clone)]
 pub struct Ctx<T> {
@@ -14,36 +14,39 @@ impl<T> Ctx<T> {
         &self.request
     }
 
-    pub(crate) fn find_peer_id(&self, object: &Value) -> crate::Result<i64> {
-        match object {
-            Value::Object(map) => {
-                if let Some(peer_id) = map.get("peer_id").and_then(|id| id.as_i64()) {
-                    return Ok(peer_id);
-                }
-                // Searching recursively only if peer_id is not found
-                for v in map.values() {
-                    if let Ok(id) = self.find_peer_id(v) {
-                        return Ok(id);
-                    }
-                }
-                Err(Error::PeerIdNotFound)
-            }
-            Value::Array(vec) => {
-                for item in vec {
-                    if let Ok(id) = self.find_peer_id(item) {
-                        return Ok(id);
-                    }
-                }
-                Err(Error::PeerIdNotFound)
-            }
-            _ => Err(Error::PeerIdNotFound),
-        }
+    /// Id of the community the update came from, known once polling has started.
+    ///
+    /// Useful when several communities share the same router, see
+    /// [`MultiPoller`](crate::MultiPoller). Replies sent through this `Ctx` always go out
+    /// with the token of that community.
+    pub fn group_id(&self) -> Option<u64> {
+        self.request.group_id()
     }
 
     pub fn new(request: Arc<RequestBuilder>, body: T) -> Ctx<T> {
         Ctx { request, body }
     }
 }
+
+pub(crate) fn find_peer_id(object: &Value) -> crate::Result<i64> {
+    find_id(object, "peer_id").ok_or(Error::PeerIdNotFound)
+}
+
+/// Searches for an integer `key` in the `object`, going deeper only if it isn't found on the top level.
+pub(crate) fn find_id(object: &Value, key: &str) -> Option<i64> {
+    match object {
+        Value::Object(map) => {
+            if let Some(id) = map.get(key).and_then(|id| id.as_i64()) {
+                return Some(id);
+            }
+            // Searching recursively only if the key is not found
+            map.values().find_map(|v| find_id(v, key))
+        }
+        Value::Array(vec) => vec.iter().find_map(|item| find_id(item, key)),
+        _ => None,
+    }
+}
+
 impl<T> std::ops::Deref for Ctx<T> {
     type Target = T;
 
@@ -56,13 +59,16 @@ impl<S> FromUpdate<S> for Ctx<Message>
 where
     S: Send + Sync,
 {
+    type Rejection = JsonRejection;
+
     async fn from_update(
         update: Update,
+        _parts: &Parts,
         _state: &S,
         request: Arc<RequestBuilder>,
-    ) -> Result<Self, ()> {
-        let message: Message =
-            serde_json::from_value(update.object).expect("Unable to parse an update");
+    ) -> Result<Self, Self::Rejection> {
+        let message: Message = serde_json::from_value(update.object)
+            .map_err(|err| JsonRejection::new(update.update_type, err))?;
         Ok(Ctx {
             request,
             body: message,
@@ -74,11 +80,14 @@ impl<S> FromUpdate<S> for Ctx<Update>
 where
     S: Send + Sync,
 {
+    type Rejection = Infallible;
+
     async fn from_update(
         update: Update,
+        _parts: &Parts,
         _state: &S,
         request: Arc<RequestBuilder>,
-    ) -> Result<Self, ()> {
+    ) -> Result<Self, Self::Rejection> {
         Ok(Ctx {
             request,
             body: update,
@@ -1,4 +1,4 @@
-use super::{FromUpdate, RequestBuilder, Update};
+use super::{FromUpdate, JsonRejection, Parts, RequestBuilder, Update};
 use crate::responses::ButtonPressCallback;
 use serde::de::DeserializeOwned;
 use std::sync::Arc;
@@ -19,13 +19,16 @@ where
     T: DeserializeOwned,
     S: Send + Sync,
 {
+    type Rejection = JsonRejection;
+
     async fn from_update(
         update: Update,
+        _parts: &Parts,
         _state: &S,
         _request: Arc<RequestBuilder>,
-    ) -> Result<Self, ()> {
-        let callback: ButtonPressCallback<T> =
-            serde_json::from_value(update.object).expect("Unable to parse a button callback");
+    ) -> Result<Self, Self::Rejection> {
+        let callback: ButtonPressCallback<T> = serde_json::from_value(update.object)
+            .map_err(|err| JsonRejection::new(update.update_type, err))?;
 
         Ok(Self(callback))
     }
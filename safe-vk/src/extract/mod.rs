@@ -1,18 +1,41 @@
+mod args;
+mod captures;
 mod ctx;
 mod keyboard;
+mod rejection;
 mod state;
 
-use crate::RequestBuilder;
+use crate::{routing::RouteMatch, RequestBuilder};
 use std::{future::Future, sync::Arc};
 
-pub use self::{ctx::Ctx, keyboard::Keyboard, state::State};
+pub(crate) use self::ctx::{find_id, find_peer_id};
+pub use self::{
+    args::{Args, ArgsError, ArgsRejection, FromArg, FromArgs},
+    captures::{Captures, CapturesRejection},
+    ctx::Ctx,
+    keyboard::Keyboard,
+    rejection::JsonRejection,
+    state::State,
+};
 
 pub type Update<T = serde_json::Value> = crate::responses::Event<T>;
 
+/// What the router matched in the update, handed to the extractors next to it. For the
+/// handler of a command it holds the trigger and the arguments after it.
+#[derive(Debug, Clone, Default)]
+pub struct Parts {
+    pub(crate) route: RouteMatch,
+}
+
 pub trait FromUpdate<S>: Sized {
+    /// Returned when the extractor can't be created from the update. The handler isn't called
+    /// then and the rejection becomes its error.
+    type Rejection: Into<crate::Error>;
+
     fn from_update(
         update: Update,
+        parts: &Parts,
         state: &S,
         request: Arc<RequestBuilder>,
-    ) -> impl Future<Output = Result<Self, ()>> + Send;
+    ) -> impl Future<Output = Result<Self, Self::Rejection>> + Send;
 }
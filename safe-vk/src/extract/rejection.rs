@@ -0,0 +1,39 @@
+use crate::Error;
+use std::fmt;
+
+/// Rejection of [`Ctx`](super::Ctx) and [`Keyboard`](super::Keyboard), returned when the
+/// object of the update isn't what the extractor expects, e.g. a `group_join` update for
+/// `Ctx<Message>`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JsonRejection {
+    /// `type` of the update that couldn't be deserialized.
+    pub update_type: String,
+    pub error: String,
+}
+
+impl JsonRejection {
+    pub(crate) fn new(update_type: String, error: serde_json::Error) -> Self {
+        Self {
+            update_type,
+            error: error.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for JsonRejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Failed to deserialize a `{}` update: {}",
+            self.update_type, self.error
+        )
+    }
+}
+
+impl std::error::Error for JsonRejection {}
+
+impl From<JsonRejection> for Error {
+    fn from(rejection: JsonRejection) -> Self {
+        Error::Rejection(rejection.to_string())
+    }
+}
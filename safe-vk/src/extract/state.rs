@@ -1,5 +1,6 @@
-use super::{FromUpdate, RequestBuilder, Update};
+use super::{FromUpdate, Parts, RequestBuilder, Update};
 use std::{
+    convert::Infallible,
     ops::{Deref, DerefMut},
     sync::Arc,
 };
@@ -39,11 +40,14 @@ where
     InnerState: FromRef<OuterState>,
     OuterState: Send + Sync,
 {
+    type Rejection = Infallible;
+
     async fn from_update(
         _update: Update,
+        _parts: &Parts,
         state: &OuterState,
         _request: Arc<RequestBuilder>,
-    ) -> Result<Self, ()> {
+    ) -> Result<Self, Self::Rejection> {
         let inner_state = InnerState::from_ref(state);
         Ok(Self(inner_state))
     }
@@ -1,7 +1,7 @@
 use std::{future::Future, pin::Pin, sync::Arc};
 
 use crate::{
-    extract::{FromUpdate, Update},
+    extract::{FromUpdate, Parts, Update},
     service::HandlerService,
     RequestBuilder, Response,
 };
@@ -9,7 +9,13 @@ use crate::{
 pub trait Handler<T, S>: Clone + Send + Sized + 'static {
     type Future: Future<Output = Response<()>> + Send + 'static;
 
-    fn call(self, update: Update, state: S, request: Arc<RequestBuilder>) -> Self::Future;
+    fn call(
+        self,
+        update: Update,
+        parts: Parts,
+        state: S,
+        request: Arc<RequestBuilder>,
+    ) -> Self::Future;
 
     fn with_state(self, state: S) -> HandlerService<Self, T, S> {
         HandlerService::new(self, state)
@@ -23,8 +29,17 @@ where
 {
     type Future = Pin<Box<dyn Future<Output = Response<()>> + Send>>;
 
-    fn call(self, _update: Update, _state: S, _request: Arc<RequestBuilder>) -> Self::Future {
-        Box::pin(async move { Ok(self().await) })
+    fn call(
+        self,
+        _update: Update,
+        _parts: Parts,
+        _state: S,
+        _request: Arc<RequestBuilder>,
+    ) -> Self::Future {
+        Box::pin(async move {
+            self().await;
+            Ok(())
+        })
     }
 }
 
@@ -42,13 +57,23 @@ macro_rules! impl_handler {
         {
             type Future = Pin<Box<dyn Future<Output = Response<()>> + Send>>;
 
-            fn call(self, update: Update, state: S, request: Arc<RequestBuilder>) -> Self::Future {
+            fn call(
+                self,
+                update: Update,
+                parts: Parts,
+                state: S,
+                request: Arc<RequestBuilder>,
+            ) -> Self::Future {
                 Box::pin(async move {
                     let state = &state;
+                    let parts = &parts;
                     let req = update;
 
                      $(
-                        let $ty = $ty::from_update(req.clone(), state, request.clone()).await.unwrap();
+                        let $ty = match $ty::from_update(req.clone(), parts, state, request.clone()).await {
+                            Ok(value) => value,
+                            Err(rejection) => return Err(rejection.into()),
+                        };
                      )*
 
 
@@ -47,7 +47,7 @@
 #![warn(
     clippy::all,
     clippy::todo,
-    clippy::empty_enum,
+    clippy::empty_enums,
     clippy::enum_glob_use,
     clippy::mem_forget,
     clippy::unused_self,
@@ -64,9 +64,7 @@
     clippy::fn_params_excessive_bools,
     clippy::exit,
     clippy::inefficient_to_string,
-    clippy::mismatched_target_os,
     clippy::await_holding_lock,
-    clippy::match_on_vec_items,
     clippy::imprecise_flops,
     clippy::suboptimal_flops,
     clippy::match_wildcard_for_single_variants,
@@ -82,29 +80,59 @@
 #![cfg_attr(test, allow(clippy::float_cmp))]
 #![cfg_attr(not(test), warn(clippy::print_stdout, clippy::dbg_macro))]
 
-mod reqwest_ext;
-
 #[macro_use]
 pub(crate) mod macros;
 
+mod batch;
+mod captcha;
+#[cfg(feature = "tokio")]
+mod dispatch;
+mod rate_limit;
+mod reqwest_ext;
+#[cfg(feature = "tokio")]
+mod retry;
+mod token_pool;
+
 pub mod api;
+#[cfg(feature = "tokio")]
+pub mod checkpoint;
+#[cfg(feature = "tokio")]
+pub mod dedup;
 pub mod extract;
 pub mod handler;
+pub mod metrics;
+#[cfg(feature = "tokio")]
+pub mod replay;
 pub mod responses;
 pub mod routing;
+#[cfg(feature = "callback")]
+pub mod serve_callback;
 pub mod service;
 #[cfg(feature = "tokio")]
 pub mod start_polling;
+#[cfg(any(test, feature = "test-util"))]
+pub mod test;
 pub use safe_vk_common::*;
 
-pub use self::reqwest_ext::{RequestBuilder, VERSION, VK, WAIT_TIME};
-pub use self::routing::SafeVk;
+pub use self::captcha::CaptchaSolver;
+pub use self::rate_limit::RateLimit;
+pub use self::reqwest_ext::{RequestBuilder, RequestConfig, VERSION, VK, WAIT_TIME};
+pub use self::routing::{route_method::PayloadMatcher, SafeVk};
+pub use self::token_pool::TokenPool;
 
 //#[cfg(feature = "macros")]
 pub use safe_vk_macros::*;
 
 #[cfg(feature = "tokio")]
-pub use self::start_polling::start_polling;
+pub use self::dispatch::DispatchMode;
+#[cfg(feature = "tokio")]
+pub use self::replay::replay;
+#[cfg(feature = "tokio")]
+pub use self::retry::{ConnectionState, RetryPolicy};
+#[cfg(feature = "callback")]
+pub use self::serve_callback::serve_callback;
+#[cfg(feature = "tokio")]
+pub use self::start_polling::{start_polling, start_user_polling, MultiPoller};
 
 #[cfg(feature = "tokio")]
 pub type Response<T> = Result<T>;
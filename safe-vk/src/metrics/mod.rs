@@ -0,0 +1,197 @@
+//! Counting what the bot does, so it can be alerted on when it silently stops working.
+//!
+//! Implement [`Metrics`] to forward the numbers into any monitoring system and pass it to
+//! [`RequestConfig::metrics`](crate::RequestConfig::metrics). Every event source, the router
+//! and every API call made with that [`RequestBuilder`](crate::RequestBuilder) report into it.
+//!
+//! With the `prometheus` feature, `PrometheusMetrics` keeps the numbers in memory and
+//! renders them in the Prometheus text format:
+//!
+//! ```ignore
+//! let metrics = Arc::new(PrometheusMetrics::new());
+//! let request = RequestBuilder::builder(&token)
+//!     .metrics(metrics.clone())
+//!     .build()?;
+//!
+//! // Serve `metrics.render()` on `/metrics` with the web framework of your choice
+//! safe_vk::start_polling(request, bot).await?;
+//! ```
+use std::time::Duration;
+
+#[cfg(feature = "prometheus")]
+mod prometheus;
+
+#[cfg(feature = "prometheus")]
+pub use self::prometheus::PrometheusMetrics;
+
+/// Receives the numbers the bot reports. Every method does nothing by default, so only the
+/// interesting ones need to be implemented.
+///
+/// Methods are called right on the hot path, so they shouldn't block.
+#[allow(unused_variables)]
+pub trait Metrics: Send + Sync + 'static {
+    /// An update of `update_type` was received from an event source.
+    fn update_received(&self, update_type: &str) {}
+
+    /// An update was routed to `route`, the trigger of a command, `watch` or `inspect`.
+    fn route_matched(&self, route: &str) {}
+
+    /// No route was found for an update of `update_type`.
+    fn route_unmatched(&self, update_type: &str) {}
+
+    /// The handler of `route` finished in `duration`, `failed` if it returned an error.
+    fn handler_finished(&self, route: &str, duration: Duration, failed: bool) {}
+
+    /// An API `method` call finished in `latency`, including the retries.
+    fn api_call(&self, method: &str, latency: Duration, outcome: CallOutcome) {}
+
+    /// Long poll is connecting again, `reason` is `error`, `key_expired` or `information_lost`.
+    fn long_poll_reconnect(&self, reason: &str) {}
+}
+
+/// How an API call reported to [`Metrics::api_call`] ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CallOutcome {
+    Ok,
+    /// VK returned an error with this `error_code`, as it was in the response even if
+    /// [`VkError`](crate::VkError) doesn't know it.
+    VkError(i32),
+    /// The call failed without a VK error, e.g. because of the network.
+    Failed,
+}
+
+impl<T> From<&crate::Result<T>> for CallOutcome {
+    fn from(result: &crate::Result<T>) -> Self {
+        match result {
+            Ok(_) => CallOutcome::Ok,
+            Err(crate::Error::VkApi(err)) => CallOutcome::VkError(err.code()),
+            Err(_) => CallOutcome::Failed,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        dispatch::Dispatcher,
+        extract::Ctx,
+        responses::Message,
+        test::{MockApi, TestBot},
+        Error, Filter, RequestBuilder, Response, SafeVk,
+    };
+    use serde_json::json;
+    use std::sync::{Arc, Mutex};
+
+    /// Remembers everything reported, except the durations.
+    #[derive(Default)]
+    struct Recorded(Mutex<Vec<String>>);
+
+    impl Recorded {
+        fn push(&self, record: String) {
+            self.0.lock().unwrap().push(record);
+        }
+
+        fn take(&self) -> Vec<String> {
+            std::mem::take(&mut *self.0.lock().unwrap())
+        }
+    }
+
+    impl Metrics for Recorded {
+        fn update_received(&self, update_type: &str) {
+            self.push(format!("update {update_type}"));
+        }
+
+        fn route_matched(&self, route: &str) {
+            self.push(format!("matched {route}"));
+        }
+
+        fn route_unmatched(&self, update_type: &str) {
+            self.push(format!("unmatched {update_type}"));
+        }
+
+        fn handler_finished(&self, route: &str, _duration: Duration, failed: bool) {
+            self.push(format!("finished {route} failed={failed}"));
+        }
+
+        fn api_call(&self, method: &str, _latency: Duration, outcome: CallOutcome) {
+            self.push(format!("call {method} {outcome:?}"));
+        }
+    }
+
+    async fn fail(_update: Ctx<Message>) -> Response<()> {
+        Err(Error::UnexpectedResponse("failed on purpose".to_owned()))
+    }
+
+    fn request(metrics: &Arc<Recorded>, api: &Arc<MockApi>) -> Arc<RequestBuilder> {
+        let mut request = RequestBuilder::builder("test")
+            .metrics(Arc::clone(metrics) as Arc<dyn Metrics>)
+            .without_rate_limit()
+            .build()
+            .unwrap();
+        request.set_mock(Arc::clone(api));
+        Arc::new(request)
+    }
+
+    #[tokio::test]
+    async fn router_reports_routes_and_handlers() {
+        let metrics = Arc::new(Recorded::default());
+        let api = Arc::new(MockApi::default());
+        let bot = SafeVk::new().command("/fail", fail, Filter::Strict);
+        let mut dispatcher = Dispatcher::new(bot, request(&metrics, &api), Default::default());
+
+        for text in ["/fail", "hello"] {
+            let update = TestBot::message(text).into();
+            dispatcher.dispatch(update).await.unwrap();
+        }
+        dispatcher.shutdown(Duration::from_secs(1)).await;
+
+        let mut records = metrics.take();
+        // Handlers run concurrently, only what is reported on dispatch is in order
+        assert_eq!(records[0], "update message_new");
+        records.sort();
+        assert_eq!(
+            records,
+            [
+                "finished /fail failed=true",
+                "matched /fail",
+                "unmatched message_new",
+                "update message_new",
+                "update message_new",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn api_calls_report_the_code_vk_sent() {
+        let metrics = Arc::new(Recorded::default());
+        let api = Arc::new(MockApi::default());
+        api.respond_once(
+            "messages.send",
+            json!({ "error": { "error_code": 7, "error_msg": "Permission denied" } }),
+        );
+        // Not a code `VkError` knows
+        api.respond_once(
+            "messages.send",
+            json!({ "error": { "error_code": 9999, "error_msg": "Something new" } }),
+        );
+        let request = request(&metrics, &api);
+
+        request.call_method("users.get", b"").await.unwrap();
+        for _ in 0..2 {
+            request
+                .call_method("messages.send", b"peer_id=1&")
+                .await
+                .unwrap_err();
+        }
+
+        assert_eq!(
+            metrics.take(),
+            [
+                "call users.get Ok",
+                "call messages.send VkError(7)",
+                "call messages.send VkError(9999)",
+            ]
+        );
+    }
+}
@@ -0,0 +1,292 @@
+use super::{CallOutcome, Metrics};
+use std::{collections::BTreeMap, fmt::Write, sync::Mutex, time::Duration};
+
+/// Upper bounds of the duration histograms, in seconds.
+const BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// [`Metrics`] kept in memory and rendered in the
+/// [Prometheus text format](https://prometheus.io/docs/instrumenting/exposition_formats/).
+///
+/// | Metric | Labels |
+/// |---|---|
+/// | `safe_vk_updates_total` | `type` |
+/// | `safe_vk_routes_matched_total` | `route` |
+/// | `safe_vk_routes_unmatched_total` | `type` |
+/// | `safe_vk_handler_duration_seconds` | `route` |
+/// | `safe_vk_handler_errors_total` | `route` |
+/// | `safe_vk_api_call_duration_seconds` | `method` |
+/// | `safe_vk_api_errors_total` | `method`, `code` (`0` if it wasn't a VK error) |
+/// | `safe_vk_long_poll_reconnects_total` | `reason` |
+#[derive(Debug, Default)]
+pub struct PrometheusMetrics {
+    inner: Mutex<Inner>,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    updates: BTreeMap<String, u64>,
+    routes_matched: BTreeMap<String, u64>,
+    routes_unmatched: BTreeMap<String, u64>,
+    handler_duration: BTreeMap<String, Histogram>,
+    handler_errors: BTreeMap<String, u64>,
+    api_duration: BTreeMap<String, Histogram>,
+    api_errors: BTreeMap<(String, i32), u64>,
+    reconnects: BTreeMap<String, u64>,
+}
+
+#[derive(Debug, Default)]
+struct Histogram {
+    buckets: [u64; BUCKETS.len()],
+    sum: f64,
+    count: u64,
+}
+
+impl PrometheusMetrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Renders every metric in the Prometheus text format, ready to be served on `/metrics`.
+    pub fn render(&self) -> String {
+        let inner = self.inner.lock().unwrap();
+        let mut out = String::new();
+
+        counter(
+            &mut out,
+            "safe_vk_updates_total",
+            "Updates received from VK.",
+            "type",
+            &inner.updates,
+        );
+        counter(
+            &mut out,
+            "safe_vk_routes_matched_total",
+            "Updates routed to a handler.",
+            "route",
+            &inner.routes_matched,
+        );
+        counter(
+            &mut out,
+            "safe_vk_routes_unmatched_total",
+            "Updates no route was found for.",
+            "type",
+            &inner.routes_unmatched,
+        );
+        histogram(
+            &mut out,
+            "safe_vk_handler_duration_seconds",
+            "Time handlers took.",
+            "route",
+            &inner.handler_duration,
+        );
+        counter(
+            &mut out,
+            "safe_vk_handler_errors_total",
+            "Handlers that returned an error.",
+            "route",
+            &inner.handler_errors,
+        );
+        histogram(
+            &mut out,
+            "safe_vk_api_call_duration_seconds",
+            "Time VK API calls took, including retries.",
+            "method",
+            &inner.api_duration,
+        );
+
+        header(
+            &mut out,
+            "safe_vk_api_errors_total",
+            "Failed VK API calls.",
+            "counter",
+        );
+        for ((method, code), value) in &inner.api_errors {
+            let _ = writeln!(
+                out,
+                "safe_vk_api_errors_total{{method=\"{}\",code=\"{code}\"}} {value}",
+                escape(method)
+            );
+        }
+
+        counter(
+            &mut out,
+            "safe_vk_long_poll_reconnects_total",
+            "Times long poll connected again.",
+            "reason",
+            &inner.reconnects,
+        );
+
+        out
+    }
+}
+
+impl Metrics for PrometheusMetrics {
+    fn update_received(&self, update_type: &str) {
+        increment(&mut self.inner.lock().unwrap().updates, update_type);
+    }
+
+    fn route_matched(&self, route: &str) {
+        increment(&mut self.inner.lock().unwrap().routes_matched, route);
+    }
+
+    fn route_unmatched(&self, update_type: &str) {
+        increment(
+            &mut self.inner.lock().unwrap().routes_unmatched,
+            update_type,
+        );
+    }
+
+    fn handler_finished(&self, route: &str, duration: Duration, failed: bool) {
+        let mut inner = self.inner.lock().unwrap();
+        observe(&mut inner.handler_duration, route, duration);
+        if failed {
+            increment(&mut inner.handler_errors, route);
+        }
+    }
+
+    fn api_call(&self, method: &str, latency: Duration, outcome: CallOutcome) {
+        let mut inner = self.inner.lock().unwrap();
+        observe(&mut inner.api_duration, method, latency);
+
+        let code = match outcome {
+            CallOutcome::Ok => return,
+            CallOutcome::VkError(code) => code,
+            CallOutcome::Failed => 0,
+        };
+        *inner
+            .api_errors
+            .entry((method.to_owned(), code))
+            .or_default() += 1;
+    }
+
+    fn long_poll_reconnect(&self, reason: &str) {
+        increment(&mut self.inner.lock().unwrap().reconnects, reason);
+    }
+}
+
+fn increment(counters: &mut BTreeMap<String, u64>, label: &str) {
+    match counters.get_mut(label) {
+        Some(value) => *value += 1,
+        None => {
+            counters.insert(label.to_owned(), 1);
+        }
+    }
+}
+
+fn observe(histograms: &mut BTreeMap<String, Histogram>, label: &str, duration: Duration) {
+    // Avoids allocating the label for every observation
+    let histogram = match histograms.get_mut(label) {
+        Some(histogram) => histogram,
+        None => histograms.entry(label.to_owned()).or_default(),
+    };
+    let seconds = duration.as_secs_f64();
+    for (bucket, bound) in histogram.buckets.iter_mut().zip(BUCKETS) {
+        if seconds <= bound {
+            *bucket += 1;
+        }
+    }
+    histogram.sum += seconds;
+    histogram.count += 1;
+}
+
+fn header(out: &mut String, name: &str, help: &str, kind: &str) {
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} {kind}");
+}
+
+fn counter(out: &mut String, name: &str, help: &str, label: &str, values: &BTreeMap<String, u64>) {
+    header(out, name, help, "counter");
+    for (value_label, value) in values {
+        let _ = writeln!(out, "{name}{{{label}=\"{}\"}} {value}", escape(value_label));
+    }
+}
+
+fn histogram(
+    out: &mut String,
+    name: &str,
+    help: &str,
+    label: &str,
+    values: &BTreeMap<String, Histogram>,
+) {
+    header(out, name, help, "histogram");
+    for (value_label, histogram) in values {
+        let value_label = escape(value_label);
+        for (bucket, bound) in histogram.buckets.iter().zip(BUCKETS) {
+            let _ = writeln!(
+                out,
+                "{name}_bucket{{{label}=\"{value_label}\",le=\"{bound}\"}} {bucket}"
+            );
+        }
+        let _ = writeln!(
+            out,
+            "{name}_bucket{{{label}=\"{value_label}\",le=\"+Inf\"}} {}",
+            histogram.count
+        );
+        let _ = writeln!(
+            out,
+            "{name}_sum{{{label}=\"{value_label}\"}} {}",
+            histogram.sum
+        );
+        let _ = writeln!(
+            out,
+            "{name}_count{{{label}=\"{value_label}\"}} {}",
+            histogram.count
+        );
+    }
+}
+
+/// Label values may contain anything, e.g. a command trigger with quotes in it.
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_reported_numbers() {
+        let metrics = PrometheusMetrics::new();
+        metrics.update_received("message_new");
+        metrics.update_received("message_new");
+        metrics.route_matched("say \"hi\"");
+        metrics.handler_finished("/fail", Duration::from_millis(30), true);
+        metrics.api_call("users.get", Duration::from_millis(3), CallOutcome::Ok);
+        metrics.api_call(
+            "messages.send",
+            Duration::from_secs(20),
+            CallOutcome::VkError(9),
+        );
+        metrics.api_call("messages.send", Duration::from_secs(1), CallOutcome::Failed);
+        metrics.long_poll_reconnect("key_expired");
+
+        let rendered = metrics.render();
+        let lines = rendered.lines().collect::<Vec<_>>();
+        for line in [
+            "# TYPE safe_vk_updates_total counter",
+            "safe_vk_updates_total{type=\"message_new\"} 2",
+            "safe_vk_routes_matched_total{route=\"say \\\"hi\\\"\"} 1",
+            "# TYPE safe_vk_handler_duration_seconds histogram",
+            "safe_vk_handler_duration_seconds_bucket{route=\"/fail\",le=\"0.025\"} 0",
+            "safe_vk_handler_duration_seconds_bucket{route=\"/fail\",le=\"0.05\"} 1",
+            "safe_vk_handler_duration_seconds_bucket{route=\"/fail\",le=\"+Inf\"} 1",
+            "safe_vk_handler_duration_seconds_count{route=\"/fail\"} 1",
+            "safe_vk_handler_errors_total{route=\"/fail\"} 1",
+            "safe_vk_api_call_duration_seconds_bucket{method=\"messages.send\",le=\"10\"} 1",
+            "safe_vk_api_call_duration_seconds_bucket{method=\"messages.send\",le=\"+Inf\"} 2",
+            "safe_vk_api_call_duration_seconds_sum{method=\"messages.send\"} 21",
+            "safe_vk_api_errors_total{method=\"messages.send\",code=\"0\"} 1",
+            "safe_vk_api_errors_total{method=\"messages.send\",code=\"9\"} 1",
+            "safe_vk_long_poll_reconnects_total{reason=\"key_expired\"} 1",
+        ] {
+            assert!(lines.contains(&line), "{line} is missing from:\n{rendered}");
+        }
+        // Successful calls are only timed
+        assert!(!rendered.contains("safe_vk_api_errors_total{method=\"users.get\""));
+    }
+}
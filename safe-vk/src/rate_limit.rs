@@ -0,0 +1,170 @@
+use std::{sync::Mutex, time::Duration};
+use tokio::time::Instant;
+
+/// How many API methods may be called in a given period, see
+/// [`RequestConfig::rate_limit`](crate::RequestConfig::rate_limit).
+///
+/// VK limits the number of requests per second depending on the token type, exceeding it
+/// makes VK return [`VkError::TooManyRequests`](crate::VkError::TooManyRequests).
+/// Long poll requests are not counted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimit {
+    requests: u32,
+    per: Duration,
+}
+
+impl RateLimit {
+    /// Allows `requests` calls per `per`, any of them can be made at once.
+    pub const fn new(requests: u32, per: Duration) -> Self {
+        Self { requests, per }
+    }
+
+    /// 20 requests per second, the limit of community tokens. Used by default, except for
+    /// [`start_user_polling`](crate::start_user_polling).
+    pub const fn community() -> Self {
+        Self::new(20, Duration::from_secs(1))
+    }
+
+    /// 3 requests per second, the limit of user and service tokens. Used by default for
+    /// [`start_user_polling`](crate::start_user_polling).
+    pub const fn user() -> Self {
+        Self::new(3, Duration::from_secs(1))
+    }
+}
+
+impl Default for RateLimit {
+    fn default() -> Self {
+        Self::community()
+    }
+}
+
+/// Token bucket shared by all clones of a [`RequestBuilder`](crate::RequestBuilder).
+#[derive(Debug)]
+pub(crate) struct RateLimiter {
+    limit: RateLimit,
+    bucket: Mutex<Bucket>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    /// Goes below zero when calls are waiting, so they are let through in order
+    tokens: f64,
+    updated: Instant,
+}
+
+impl RateLimiter {
+    pub(crate) fn new(limit: RateLimit) -> Self {
+        Self {
+            limit,
+            bucket: Mutex::new(Bucket {
+                tokens: limit.requests.into(),
+                updated: Instant::now(),
+            }),
+        }
+    }
+
+    /// Waits until one more request can be made.
+    pub(crate) async fn acquire(&self) {
+        let capacity = f64::from(self.limit.requests);
+        let per_second = capacity / self.limit.per.as_secs_f64();
+        if !per_second.is_finite() || per_second <= 0.0 {
+            return;
+        }
+
+        let wait = {
+            let mut bucket = self.bucket.lock().unwrap();
+            let now = Instant::now();
+            let refilled = now.duration_since(bucket.updated).as_secs_f64() * per_second;
+            bucket.tokens = (bucket.tokens + refilled).min(capacity) - 1.0;
+            bucket.updated = now;
+
+            (bucket.tokens < 0.0).then(|| Duration::from_secs_f64(-bucket.tokens / per_second))
+        };
+
+        if let Some(wait) = wait {
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    /// Calls `acquire` and tells how long it waited.
+    async fn acquire(limiter: &RateLimiter) -> Duration {
+        let start = Instant::now();
+        limiter.acquire().await;
+        start.elapsed()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn burst_then_waits_for_refill() {
+        let limiter = RateLimiter::new(RateLimit::new(3, Duration::from_secs(1)));
+
+        for _ in 0..3 {
+            assert_eq!(acquire(&limiter).await, Duration::ZERO);
+        }
+        let waited = acquire(&limiter).await;
+        assert!(
+            waited >= Duration::from_millis(333) && waited <= Duration::from_millis(334),
+            "waited {waited:?}"
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn waiting_calls_are_spread_out() {
+        let limiter = Arc::new(RateLimiter::new(RateLimit::new(2, Duration::from_secs(1))));
+        acquire(&limiter).await;
+        acquire(&limiter).await;
+
+        let start = Instant::now();
+        let waiting: Vec<_> = (0..3)
+            .map(|_| {
+                let limiter = Arc::clone(&limiter);
+                tokio::spawn(async move {
+                    limiter.acquire().await;
+                    start.elapsed()
+                })
+            })
+            .collect();
+
+        let mut waited = Vec::new();
+        for task in waiting {
+            waited.push(task.await.unwrap());
+        }
+        waited.sort();
+        for (waited, expected) in waited.into_iter().zip([500, 1000, 1500]) {
+            let expected = Duration::from_millis(expected);
+            assert!(
+                waited >= expected && waited <= expected + Duration::from_millis(1),
+                "waited {waited:?}, expected {expected:?}"
+            );
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn idle_time_refills_up_to_capacity() {
+        let limiter = RateLimiter::new(RateLimit::new(3, Duration::from_secs(1)));
+        for _ in 0..3 {
+            acquire(&limiter).await;
+        }
+
+        tokio::time::sleep(Duration::from_secs(60)).await;
+
+        for _ in 0..3 {
+            assert_eq!(acquire(&limiter).await, Duration::ZERO);
+        }
+        assert!(acquire(&limiter).await > Duration::ZERO);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn zero_limit_is_not_enforced() {
+        let limiter = RateLimiter::new(RateLimit::new(0, Duration::from_secs(1)));
+
+        for _ in 0..10 {
+            assert_eq!(acquire(&limiter).await, Duration::ZERO);
+        }
+    }
+}
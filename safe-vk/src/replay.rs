@@ -0,0 +1,233 @@
+//! Recording updates and feeding them back into a router later.
+//!
+//! [`Polling::record`](crate::start_polling::Polling::record) appends every update it
+//! receives to a JSONL file, one [`RecordedEvent`] per line. [`replay`] reads such a file
+//! and dispatches the updates again, keeping the original pauses between them or
+//! speeding them up, which is handy for reproducing bugs without touching VK:
+//!
+//! ```ignore
+//! // In production
+//! safe_vk::start_polling(&token, bot).record("updates.jsonl").await?;
+//!
+//! // Later, ten times faster
+//! safe_vk::replay(&token, "updates.jsonl", bot).speed(10.0).await?;
+//! ```
+//!
+//! A recording can also be fed into a `test::TestBot` (with the `test-util` feature) with
+//! [`load`]:
+//!
+//! ```ignore
+//! for recorded in replay::load("updates.jsonl").await? {
+//!     bot.send(recorded.event).await?;
+//! }
+//! ```
+use crate::{
+    dispatch::{dispatch_options, DispatchOptions, Dispatcher},
+    extract::Update,
+    service::Service,
+    start_polling::SHUTDOWN_TIMEOUT,
+    RequestBuilder, Response,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    future::{Future, IntoFuture},
+    marker::PhantomData,
+    path::{Path, PathBuf},
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::{
+    fs::{File, OpenOptions},
+    io::AsyncWriteExt,
+};
+
+/// A line of a recording.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecordedEvent {
+    /// When the update was received, in milliseconds since the Unix epoch.
+    pub time: u64,
+    /// The update as it was handed to the router.
+    pub event: Update,
+}
+
+/// Reads every update of the recording at `path`.
+pub async fn load(path: impl AsRef<Path>) -> Response<Vec<RecordedEvent>> {
+    let contents = tokio::fs::read_to_string(path).await?;
+    contents
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| Ok(serde_json::from_str(line)?))
+        .collect()
+}
+
+/// Appends received updates to a recording, see [`Polling::record`](crate::start_polling::Polling::record).
+pub(crate) struct Recorder {
+    file: File,
+}
+
+impl Recorder {
+    pub(crate) async fn open(path: &Path) -> Response<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .await?;
+
+        Ok(Self { file })
+    }
+
+    /// Writes `event` as a new line and flushes it, so the recording is complete even if the
+    /// bot is killed. A failed write is only logged, since losing a line of the recording is
+    /// better than stopping the bot.
+    pub(crate) async fn record(&mut self, event: &Update) {
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |time| time.as_millis() as u64);
+
+        let mut line = match serde_json::to_vec(&RecordedEvent {
+            time,
+            event: event.clone(),
+        }) {
+            Ok(line) => line,
+            Err(err) => {
+                warn!("Failed to record event {}: {err}", event.event_id);
+                return;
+            }
+        };
+        line.push(b'\n');
+
+        let written = match self.file.write_all(&line).await {
+            Ok(()) => self.file.flush().await,
+            Err(err) => Err(err),
+        };
+        if let Err(err) = written {
+            warn!("Failed to record event {}: {err}", event.event_id);
+        }
+    }
+}
+
+pub struct Replay<M, S> {
+    request: RequestBuilder,
+    path: PathBuf,
+    safevk: M,
+    speed: f64,
+    options: DispatchOptions,
+    _marker: PhantomData<S>,
+}
+
+/// Dispatches the updates recorded at `path` into `safevk`, waiting between them as long as
+/// they were apart when recorded.
+///
+/// Handlers call the API with `token` as usual. The future resolves once every update has
+/// been dispatched and the handlers are done.
+pub fn replay<M, S>(
+    token: impl Into<RequestBuilder>,
+    path: impl Into<PathBuf>,
+    safevk: M,
+) -> Replay<M, S>
+where
+    M: Service<Update, Response = ()> + Send + Clone + 'static,
+    <M as Service<Update>>::Future: Send + 'static,
+{
+    Replay {
+        request: token.into(),
+        path: path.into(),
+        safevk,
+        speed: 1.0,
+        options: DispatchOptions::default(),
+        _marker: PhantomData,
+    }
+}
+
+impl<M, S> Replay<M, S> {
+    /// How many times faster than recorded the updates are dispatched, `1.0` by default.
+    /// [`f64::INFINITY`] dispatches them without any pauses.
+    pub fn speed(mut self, speed: f64) -> Self {
+        self.speed = speed;
+        self
+    }
+}
+
+dispatch_options!(Replay);
+
+impl<M, S> IntoFuture for Replay<M, S>
+where
+    M: Service<Update, Response = S> + Send + Clone + 'static,
+    <M as Service<Update>>::Future: Send + 'static,
+    S: Send + Clone + 'static,
+{
+    type Output = Response<()>;
+    type IntoFuture = ReplayFuture;
+
+    fn into_future(self) -> Self::IntoFuture {
+        ReplayFuture(Box::pin(async move {
+            let Self {
+                request,
+                path,
+                safevk,
+                speed,
+                options,
+                _marker: _,
+            } = self;
+
+            let recording = load(&path).await?;
+            let mut dispatcher = Dispatcher::new(safevk, Arc::new(request), options);
+
+            let mut previous = None;
+            for recorded in recording {
+                if let Some(previous) = previous {
+                    let pause = Duration::from_millis(recorded.time.saturating_sub(previous));
+                    if speed > 0.0 && speed.is_finite() && !pause.is_zero() {
+                        tokio::time::sleep(pause.div_f64(speed)).await;
+                    }
+                }
+                previous = Some(recorded.time);
+
+                dispatcher.dispatch(recorded.event).await?;
+            }
+
+            dispatcher.shutdown(SHUTDOWN_TIMEOUT).await;
+            Ok(())
+        }))
+    }
+}
+
+pub struct ReplayFuture(futures_util::future::BoxFuture<'static, Response<()>>);
+
+impl Future for ReplayFuture {
+    type Output = Response<()>;
+
+    #[inline]
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        self.0.as_mut().poll(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::TestBot;
+
+    #[tokio::test]
+    async fn recorded_events_are_on_disk_right_away() {
+        let path = std::env::temp_dir().join(format!("safe-vk-record-{}.jsonl", fastrand::u64(..)));
+        let mut recorder = Recorder::open(&path).await.unwrap();
+        let first = Update::from(TestBot::message("/first"));
+        let second = Update::from(TestBot::message("/second"));
+
+        recorder.record(&first).await;
+        recorder.record(&second).await;
+        // Read while the recorder is still open, as after a crash
+        let recorded = load(&path).await;
+        let _ = std::fs::remove_file(&path);
+
+        let ids = recorded
+            .unwrap()
+            .into_iter()
+            .map(|recorded| recorded.event.event_id)
+            .collect::<Vec<_>>();
+        assert_eq!(ids, [first.event_id, second.event_id]);
+    }
+}
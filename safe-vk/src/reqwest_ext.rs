@@ -1,13 +1,26 @@
 use super::{
+    batch::Batcher,
+    captcha::CaptchaSolver,
+    metrics::{CallOutcome, Metrics},
     parse_response,
-    responses::{LongPollResponse, LongPollSession},
+    rate_limit::{RateLimit, RateLimiter},
+    responses::{LongPollResponse, LongPollSession, UserLongPollResponse},
+    token_pool::{PooledToken, TokenPool},
     Error, Result, VkError,
 };
+#[cfg(any(test, feature = "test-util"))]
+use crate::test::MockApi;
+use reqwest::header::CONTENT_TYPE;
 use serde::Serialize;
 use serde_json::Value;
-use std::sync::Arc;
+use std::{
+    borrow::Cow,
+    fmt,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 use tokio::sync::Mutex;
-use urlencoding::encode;
+use urlencoding::{decode, encode};
 
 /// A [`RequestBuilder`] responsible for establishing connections to [VK Long Poll](https://dev.vk.com/en/api/bots-long-poll/getting-started)
 /// and sending method requests to the VK API.
@@ -15,17 +28,268 @@ use urlencoding::encode;
 /// This struct holds your `access_token` and `group_id` obtained from VK.
 /// For more information about how to obtai an access token, see
 /// [official documentation](https://dev.vk.com/en/api/community-messages/getting-started#Getting%20the%20Access%20Key%20in%20Community%20Settings).
-#[derive(Clone, Debug)]
+/// Use [`RequestBuilder::builder`] to point it at another server, change the API version
+/// or tune the underlying HTTP client.
+#[derive(Clone)]
 pub struct RequestBuilder {
     client: reqwest::Client,
     access_token: String,
+    base_url: String,
+    version: String,
+    wait: u8,
+    timeout: Option<Duration>,
+    limiter: Option<Arc<RateLimiter>>,
+    /// The limit wasn't configured, so it follows the kind of the token.
+    default_rate_limit: bool,
+    rate_limit_retries: u32,
+    rate_limit_delay: Duration,
+    batcher: Option<Arc<Batcher>>,
+    captcha_solver: Option<Arc<dyn CaptchaSolver>>,
+    token_pool: Option<Arc<TokenPool>>,
+    metrics: Option<Arc<dyn Metrics>>,
+    #[cfg(any(test, feature = "test-util"))]
+    mock: Option<Arc<MockApi>>,
+    group_id: Option<u64>,
     _ts: Arc<Mutex<Option<String>>>,
     _session: Arc<Mutex<Option<LongPollSession>>>,
 }
 
-pub const VK: &'static str = "https://api.vk.com/method";
+pub const VK: &str = "https://api.vk.com/method";
 pub const WAIT_TIME: u8 = 25;
-pub const VERSION: &'static str = "5.199";
+pub const VERSION: &str = "5.199";
+
+/// How many captchas in a row are solved for a single call before giving up.
+const CAPTCHA_ATTEMPTS: u32 = 3;
+/// How many times a rate limited call is repeated by default.
+const RATE_LIMIT_RETRIES: u32 = 3;
+/// How much longer every next attempt of a rate limited call waits by default.
+const RATE_LIMIT_DELAY: Duration = Duration::from_secs(1);
+
+/// Configuration of a [`RequestBuilder`], created by [`RequestBuilder::builder`].
+///
+/// ```ignore
+/// let request = RequestBuilder::builder(&token)
+///     .base_url("http://127.0.0.1:8081/method")
+///     .timeout(Duration::from_secs(10))
+///     .user_agent("my-bot/1.0")
+///     .build()?;
+///
+/// safe_vk::start_polling(request, bot).await?;
+/// ```
+pub struct RequestConfig {
+    access_token: String,
+    base_url: String,
+    version: String,
+    wait: u8,
+    timeout: Option<Duration>,
+    connect_timeout: Option<Duration>,
+    proxy: Option<reqwest::Proxy>,
+    user_agent: Option<String>,
+    client: Option<reqwest::Client>,
+    rate_limit: Option<RateLimit>,
+    default_rate_limit: bool,
+    rate_limit_retries: u32,
+    rate_limit_delay: Duration,
+    batch_window: Option<Duration>,
+    captcha_solver: Option<Arc<dyn CaptchaSolver>>,
+    token_pool: Option<TokenPool>,
+    metrics: Option<Arc<dyn Metrics>>,
+}
+
+/// Shown instead of access tokens, so they don't end up in logs.
+pub(crate) const REDACTED: &str = "<redacted>";
+
+impl fmt::Debug for RequestBuilder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RequestBuilder")
+            .field("access_token", &REDACTED)
+            .field("base_url", &self.base_url)
+            .field("version", &self.version)
+            .field("wait", &self.wait)
+            .field("timeout", &self.timeout)
+            .field("limiter", &self.limiter)
+            .field("batcher", &self.batcher)
+            .field("token_pool", &self.token_pool)
+            .field("group_id", &self.group_id)
+            .finish_non_exhaustive()
+    }
+}
+
+impl fmt::Debug for RequestConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RequestConfig")
+            .field("access_token", &REDACTED)
+            .field("base_url", &self.base_url)
+            .field("version", &self.version)
+            .field("wait", &self.wait)
+            .field("timeout", &self.timeout)
+            .field("rate_limit", &self.rate_limit)
+            .field("batch_window", &self.batch_window)
+            .field("token_pool", &self.token_pool)
+            .finish_non_exhaustive()
+    }
+}
+
+impl RequestConfig {
+    /// Base URL of the API methods, [`VK`] by default.
+    pub fn base_url(mut self, url: impl Into<String>) -> Self {
+        self.base_url = url.into().trim_end_matches('/').to_owned();
+        self
+    }
+
+    /// API version sent with every request, [`VERSION`] by default.
+    pub fn version(mut self, version: impl Into<String>) -> Self {
+        self.version = version.into();
+        self
+    }
+
+    /// How many seconds the long poll server may hold a request, [`WAIT_TIME`] by default.
+    /// VK doesn't allow more than 90.
+    pub fn wait(mut self, wait: u8) -> Self {
+        self.wait = wait.min(90);
+        self
+    }
+
+    /// Timeout of every request. Long poll requests get `wait` seconds on top of it,
+    /// since the server holds them that long when there are no events.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Timeout of establishing a connection. Ignored if a [`client`](Self::client) is set.
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = Some(timeout);
+        self
+    }
+
+    /// Sends every request through `proxy`. Ignored if a [`client`](Self::client) is set.
+    pub fn proxy(mut self, proxy: reqwest::Proxy) -> Self {
+        self.proxy = Some(proxy);
+        self
+    }
+
+    /// `User-Agent` header of every request. Ignored if a [`client`](Self::client) is set.
+    pub fn user_agent(mut self, user_agent: impl Into<String>) -> Self {
+        self.user_agent = Some(user_agent.into());
+        self
+    }
+
+    /// Uses an already configured client, e.g. one shared with the rest of the application.
+    pub fn client(mut self, client: reqwest::Client) -> Self {
+        self.client = Some(client);
+        self
+    }
+
+    /// Limits how often API methods are called, calls over the limit wait for their turn.
+    /// Defaults to [`RateLimit::community`], or to [`RateLimit::user`] once the token is used
+    /// for [`start_user_polling`](crate::start_user_polling). Set [`RateLimit::user`] here
+    /// when calling methods with a user token in any other way.
+    ///
+    /// The limit is shared by all clones of the built [`RequestBuilder`].
+    pub fn rate_limit(mut self, limit: RateLimit) -> Self {
+        self.rate_limit = Some(limit);
+        self.default_rate_limit = false;
+        self
+    }
+
+    /// Calls API methods as fast as they come, e.g. when the limit is handled elsewhere.
+    pub fn without_rate_limit(mut self) -> Self {
+        self.rate_limit = None;
+        self.default_rate_limit = false;
+        self
+    }
+
+    /// How many times a call is repeated when VK still answers with
+    /// [`TooManyRequests`](VkError::TooManyRequests) or
+    /// [`TooManySimilarActions`](VkError::TooManySimilarActions), waiting `delay` more before
+    /// every next attempt. Defaults to 3 times with a 1 second step, `0` disables retrying.
+    pub fn rate_limit_retries(mut self, retries: u32, delay: Duration) -> Self {
+        self.rate_limit_retries = retries;
+        self.rate_limit_delay = delay;
+        self
+    }
+
+    /// Sends API methods called within `window` of each other as a single
+    /// [`execute`](https://dev.vk.com/en/method/execute) request, up to 25 calls at once.
+    ///
+    /// Every call still gets its own result or error. Useful when a lot of calls are made
+    /// at once, e.g. a broadcast sending `messages.send` to many peers, since the whole
+    /// batch counts as one request towards the [`rate_limit`](Self::rate_limit).
+    pub fn batch(mut self, window: Duration) -> Self {
+        self.batch_window = Some(window);
+        self
+    }
+
+    /// Solves captchas with `solver` when VK answers with
+    /// [`CaptchaRequired`](VkError::CaptchaRequired), the call is then repeated with the
+    /// solution. Without a solver the error is returned to the caller.
+    pub fn captcha_solver(mut self, solver: impl CaptchaSolver) -> Self {
+        self.captcha_solver = Some(Arc::new(solver));
+        self
+    }
+
+    /// Spreads API method calls across the tokens of `pool`, see [`TokenPool`].
+    ///
+    /// The token the builder was created with is still used for long poll.
+    pub fn token_pool(mut self, pool: TokenPool) -> Self {
+        self.token_pool = Some(pool);
+        self
+    }
+
+    /// Reports updates, handlers and API calls into `metrics`, see [`metrics`](crate::metrics).
+    pub fn metrics(mut self, metrics: Arc<dyn Metrics>) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    pub fn build(self) -> Result<RequestBuilder> {
+        let client = match self.client {
+            Some(client) => client,
+            None => {
+                let mut builder = reqwest::Client::builder();
+                if let Some(timeout) = self.connect_timeout {
+                    builder = builder.connect_timeout(timeout);
+                }
+                if let Some(proxy) = self.proxy {
+                    builder = builder.proxy(proxy);
+                }
+                if let Some(user_agent) = self.user_agent {
+                    builder = builder.user_agent(user_agent);
+                }
+                builder.build()?
+            }
+        };
+
+        Ok(RequestBuilder {
+            client,
+            access_token: self.access_token,
+            base_url: self.base_url,
+            version: self.version,
+            wait: self.wait,
+            timeout: self.timeout,
+            limiter: self
+                .rate_limit
+                .map(|limit| Arc::new(RateLimiter::new(limit))),
+            default_rate_limit: self.default_rate_limit,
+            rate_limit_retries: self.rate_limit_retries,
+            rate_limit_delay: self.rate_limit_delay,
+            batcher: self
+                .batch_window
+                .map(|window| Arc::new(Batcher::new(window))),
+            captcha_solver: self.captcha_solver,
+            token_pool: self
+                .token_pool
+                .map(|pool| Arc::new(pool.rate_limit(self.rate_limit))),
+            metrics: self.metrics,
+            #[cfg(any(test, feature = "test-util"))]
+            mock: None,
+            group_id: None,
+            _ts: Arc::new(Mutex::new(None)),
+            _session: Arc::new(Mutex::new(None)),
+        })
+    }
+}
 
 macro_rules! request {
     ($method:ident) => {
@@ -37,49 +301,304 @@ macro_rules! request {
             query: &[u8],
             body: T,
         ) -> Result<Value> {
-            // This is totally fine!!! "itoa" library guarantee that it will return valid utf8,
-            // hence it's safe to use "unsafe" block here!!! It will make this code blazingly fast!
-            #[cfg(feature = "unsafe")]
-            let query = unsafe { std::str::from_utf8_unchecked(query) };
+            let params = query_str(query);
+            let query = encode_query(query);
 
-            #[cfg(not(feature = "unsafe"))]
-            let query = std::str::from_utf8(query).unwrap();
+            // Long poll requests have no method, they are told apart by the server address
+            let (route, name) = if method.is_empty() {
+                (Route::LongPoll, url)
+            } else {
+                (Route::Main, method)
+            };
 
-            let query = encode(query).replace("%3D", "=").replace("%26", "&");
+            let url = if method.is_empty() {
+                format!("{}?{}v={}", url, query, self.version)
+            } else {
+                format!("{}/{}?{}v={}", url, method, query, self.version)
+            };
 
-            let response = self
-                .client
-                .$method(if method.is_empty() {
-                    format!("{}?{}v={}", url, query, VERSION)
+            // Serialized once, since the request may be repeated
+            let body = serde_json::to_vec(&body)?;
+
+            self.send(route, name, params, |token| {
+                self.client
+                    .$method(&url)
+                    .bearer_auth(token)
+                    .header(CONTENT_TYPE, "application/json")
+                    .body(body.clone())
+            })
+            .await
+        }
+    };
+}
+
+impl RequestBuilder {
+    /// Sends the request `build` makes for a call of `method` with `params`, see
+    /// [`RequestBuilder::try_send`].
+    async fn send(
+        &self,
+        route: Route,
+        method: &str,
+        params: &str,
+        build: impl Fn(&str) -> reqwest::RequestBuilder + Send + Sync,
+    ) -> Result<Value> {
+        let start = Instant::now();
+
+        #[cfg(feature = "tracing")]
+        let span = tracing::info_span!(
+            "vk_method",
+            method,
+            latency_ms = tracing::field::Empty,
+            error_code = tracing::field::Empty,
+        );
+        let mut error_code = None;
+        #[cfg(feature = "tracing")]
+        let result = tracing::Instrument::instrument(
+            self.try_send(route, method, params, build, &mut error_code),
+            span.clone(),
+        )
+        .await;
+        #[cfg(not(feature = "tracing"))]
+        let result = self
+            .try_send(route, method, params, build, &mut error_code)
+            .await;
+
+        let latency = start.elapsed();
+        // The code from the response, since codes unknown to `VkError` turn into `1`
+        let outcome = match (&result, error_code) {
+            (Err(Error::VkApi(_)), Some(code)) => CallOutcome::VkError(code),
+            _ => CallOutcome::from(&result),
+        };
+
+        #[cfg(feature = "tracing")]
+        {
+            span.record("latency_ms", latency.as_millis() as u64);
+            if let CallOutcome::VkError(code) = outcome {
+                span.record("error_code", code);
+            }
+        }
+
+        // Long poll requests are held by the server, their latency means nothing
+        if let (Some(metrics), false) = (&self.metrics, route == Route::LongPoll) {
+            metrics.api_call(method, latency, outcome);
+        }
+
+        result
+    }
+
+    /// Sends the request `build` makes with the token picked for `route`, waiting for the
+    /// rate limiter and repeating it when VK answers that the limit was exceeded anyway.
+    ///
+    /// The `error_code` of the last VK error is stored into `error_code`.
+    async fn try_send(
+        &self,
+        route: Route,
+        method: &str,
+        params: &str,
+        build: impl Fn(&str) -> reqwest::RequestBuilder + Send + Sync,
+        error_code: &mut Option<i32>,
+    ) -> Result<Value> {
+        let long_poll = route == Route::LongPoll;
+        let mut attempt = 0;
+        let mut token_attempt = 0;
+        let mut captcha_attempt = 0;
+        // `captcha_sid` and `captcha_key` of the last solved captcha
+        let mut captcha: Option<(String, String)> = None;
+        loop {
+            let pooled = match (route, &self.token_pool) {
+                (Route::Pooled, Some(pool)) => pool.next(),
+                _ => None,
+            };
+
+            // Long poll servers don't count towards the limit
+            match (pooled, &self.limiter) {
+                (Some(pooled), _) => pooled.acquire().await,
+                (None, Some(limiter)) if !long_poll => limiter.acquire().await,
+                _ => {}
+            }
+
+            let token = pooled.map_or(self.access_token.as_str(), PooledToken::token);
+            let mut request = build(token);
+            if let Some((sid, key)) = &captcha {
+                request = request.query(&[("captcha_sid", sid), ("captcha_key", key)]);
+            }
+            if let Some(timeout) = self.timeout {
+                // Long poll servers hold the request for up to `wait` seconds
+                request = request.timeout(if long_poll {
+                    timeout + Duration::from_secs(self.wait.into())
                 } else {
-                    format!("{}/{}?{}v={}", url, method, query, VERSION)
-                })
-                .bearer_auth(&self.access_token)
-                .json(&body)
-                .send()
-                .await?;
+                    timeout
+                });
+            }
 
-            let json: Value = response.json().await?;
+            let json = self.fetch(request, method, params, token).await?;
             if let Some(err) = json.get("error") {
-                return Err(Error::VkApi(VkError::from_vk_error_json(err)));
+                *error_code = err
+                    .get("error_code")
+                    .and_then(Value::as_i64)
+                    .and_then(|code| i32::try_from(code).ok());
+                let err = VkError::from_vk_error_json(err);
+                let limited = matches!(
+                    err,
+                    VkError::TooManyRequests(_) | VkError::TooManySimilarActions(_)
+                );
+
+                if limited && attempt < self.rate_limit_retries {
+                    attempt += 1;
+                    #[cfg(feature = "tracing")]
+                    tracing::debug!(attempt, "rate limited by VK, repeating the call");
+                    tokio::time::sleep(self.rate_limit_delay * attempt).await;
+                    continue;
+                }
+
+                if let (Some(pooled), Some(pool)) = (pooled, &self.token_pool) {
+                    let exhausted = matches!(
+                        err,
+                        VkError::InvalidCommunityAccessToken(_)
+                            | VkError::MethodCallLimitReached(_)
+                    );
+
+                    if exhausted && token_attempt < pool.len() {
+                        token_attempt += 1;
+                        #[cfg(feature = "tracing")]
+                        tracing::warn!(error_code = err.code(), "pooled token is cooling down");
+                        pool.cool(pooled);
+                        continue;
+                    }
+                }
+
+                if let (
+                    VkError::CaptchaRequired {
+                        captcha_sid,
+                        captcha_img,
+                        ..
+                    },
+                    Some(solver),
+                ) = (&err, &self.captcha_solver)
+                {
+                    if captcha_attempt < CAPTCHA_ATTEMPTS {
+                        captcha_attempt += 1;
+                        let key = solver.solve(captcha_img).await?;
+                        captcha = Some((captcha_sid.clone(), key));
+                        continue;
+                    }
+                }
+
+                return Err(Error::VkApi(err));
             }
 
-            Ok(json)
+            return Ok(json);
         }
-    };
-}
+    }
+
+    /// Sends `request` and reads its JSON, the mock API answers it instead if there is one.
+    #[cfg_attr(not(any(test, feature = "test-util")), allow(unused_variables))]
+    async fn fetch(
+        &self,
+        request: reqwest::RequestBuilder,
+        method: &str,
+        params: &str,
+        token: &str,
+    ) -> Result<Value> {
+        #[cfg(any(test, feature = "test-util"))]
+        if let Some(mock) = &self.mock {
+            return Ok(mock.answer(method, params, token));
+        }
+
+        let response = request.send().await.map_err(Error::Transport)?;
+
+        // VK being down is a network problem rather than an API error, so it can be retried
+        if response.status().is_server_error() {
+            response.error_for_status_ref().map_err(Error::Transport)?;
+        }
+
+        Ok(response.json().await?)
+    }
 
-impl RequestBuilder {
     /// Creates a new instance of [RequestBuilder]
     pub fn new(access_token: impl Into<String>) -> Self {
         RequestBuilder {
             client: reqwest::Client::new(),
             access_token: access_token.into(),
+            base_url: VK.to_owned(),
+            version: VERSION.to_owned(),
+            wait: WAIT_TIME,
+            timeout: None,
+            limiter: Some(Arc::new(RateLimiter::new(RateLimit::default()))),
+            default_rate_limit: true,
+            rate_limit_retries: RATE_LIMIT_RETRIES,
+            rate_limit_delay: RATE_LIMIT_DELAY,
+            batcher: None,
+            captcha_solver: None,
+            token_pool: None,
+            metrics: None,
+            #[cfg(any(test, feature = "test-util"))]
+            mock: None,
+            group_id: None,
             _ts: Arc::new(Mutex::new(None)),
             _session: Arc::new(Mutex::new(None)),
         }
     }
 
+    /// Starts configuring a [RequestBuilder], see [`RequestConfig`].
+    pub fn builder(access_token: impl Into<String>) -> RequestConfig {
+        RequestConfig {
+            access_token: access_token.into(),
+            base_url: VK.to_owned(),
+            version: VERSION.to_owned(),
+            wait: WAIT_TIME,
+            timeout: None,
+            connect_timeout: None,
+            proxy: None,
+            user_agent: None,
+            client: None,
+            rate_limit: Some(RateLimit::default()),
+            default_rate_limit: true,
+            rate_limit_retries: RATE_LIMIT_RETRIES,
+            rate_limit_delay: RATE_LIMIT_DELAY,
+            batch_window: None,
+            captcha_solver: None,
+            token_pool: None,
+            metrics: None,
+        }
+    }
+
+    /// Base URL of the API methods, [`VK`] unless configured otherwise.
+    pub fn base_url(&self) -> &str {
+        &self.base_url
+    }
+
+    /// Id of the community the token belongs to, set once long polling has started.
+    pub fn group_id(&self) -> Option<u64> {
+        self.group_id
+    }
+
+    pub(crate) fn set_group_id(&mut self, group_id: u64) {
+        self.group_id = Some(group_id);
+    }
+
+    /// Switches to the limit of user tokens, unless a limit was configured.
+    pub(crate) fn set_user_rate_limit(&mut self) {
+        if self.default_rate_limit {
+            self.limiter = Some(Arc::new(RateLimiter::new(RateLimit::user())));
+        }
+    }
+
+    pub(crate) fn metrics(&self) -> Option<&Arc<dyn Metrics>> {
+        self.metrics.as_ref()
+    }
+
+    /// Answers API calls with `mock` instead of sending them, see [`TestBot`](crate::test::TestBot).
+    #[cfg(any(test, feature = "test-util"))]
+    pub(crate) fn set_mock(&mut self, mock: Arc<MockApi>) {
+        self.mock = Some(mock);
+    }
+
+    /// API version sent with every request, [`VERSION`] unless configured otherwise.
+    pub fn version(&self) -> &str {
+        &self.version
+    }
+
     pub async fn update_session(&self, new_session: LongPollSession) {
         *self._session.lock().await = Some(new_session)
     }
@@ -88,13 +607,18 @@ impl RequestBuilder {
         *self._ts.lock().await = Some(new_ts);
     }
 
+    /// Returns the `ts` of the last received long poll batch, if any.
+    pub async fn ts(&self) -> Option<String> {
+        self._ts.lock().await.clone()
+    }
+
     pub(crate) async fn get_long_poll_server(&self, group_id: u64) -> Result<LongPollSession> {
         let response = parse_response!(
             self.post(
-                VK,
+                &self.base_url,
                 "groups.getLongPollServer",
                 format!("group_id={}&", group_id).as_bytes(),
-                {}
+                ()
             )
             .await?,
             LongPollSession
@@ -104,14 +628,10 @@ impl RequestBuilder {
     }
 
     pub async fn get_group_id(&self) -> Result<u64> {
-        let response = self.post(VK, "groups.getById", b"", {}).await?;
-        let group_id = response["response"]["groups"][0]
-            .get("id")
-            .unwrap()
+        let response = self.post(&self.base_url, "groups.getById", b"", ()).await?;
+        response["response"]["groups"][0]["id"]
             .as_u64()
-            .unwrap();
-
-        Ok(group_id)
+            .ok_or_else(|| Error::UnexpectedResponse(response.to_string()))
     }
 
     pub async fn build_long_poll_request(&self, group_id: u64) -> Result<LongPollResponse<Value>> {
@@ -127,24 +647,23 @@ impl RequestBuilder {
         let ts = prev_ts.as_ref().unwrap_or(&longpoll.ts);
 
         let query = format!(
-            "act=a_check&key={}&ts={}&wait={}",
-            longpoll.key, ts, WAIT_TIME
+            "act=a_check&key={}&ts={}&wait={}&",
+            longpoll.key, ts, self.wait
         );
 
         let response = self
-            .post(&longpoll.server, "", query.as_bytes(), {})
+            .post(&longpoll.server, "", query.as_bytes(), ())
             .await?;
 
         let mut response = parse_response!(response, LongPollResponse<Value>)?;
 
         if let Some(ts) = response.ts.take() {
             *prev_ts = Some(ts);
-            drop(prev_ts);
         }
 
         match response.failed {
             Some(1) => Err(Error::EventsOutdated {
-                new_ts: response.ts.unwrap(),
+                new_ts: prev_ts.clone().unwrap_or_default(),
             }),
             Some(2) => Err(Error::KeyExpired),
             Some(3) => Err(Error::InformationLost),
@@ -152,6 +671,257 @@ impl RequestBuilder {
         }
     }
 
+    /// Calls an API `method`, this is what every method of [`api`](crate::api) ends up in.
+    ///
+    /// The call is batched with others if [`RequestConfig::batch`] is enabled.
+    pub async fn call_method(&self, method: &str, query: &[u8]) -> Result<Value> {
+        match &self.batcher {
+            Some(batcher) if method != "execute" => {
+                batcher.call(self, method, query_str(query)).await
+            }
+            _ => {
+                let url = format!(
+                    "{}/{}?{}v={}",
+                    self.base_url,
+                    method,
+                    encode_query(query),
+                    self.version
+                );
+                self.send(Route::Pooled, method, query_str(query), |token| {
+                    self.client.post(&url).bearer_auth(token)
+                })
+                .await
+            }
+        }
+    }
+
+    /// Runs VKScript `code` through the `execute` method. Unlike other methods, the code
+    /// is sent in the request body, so it isn't limited by the URL length.
+    pub(crate) async fn execute(&self, code: &str) -> Result<Value> {
+        let url = format!("{}/execute?v={}", self.base_url, self.version);
+        let params = format!("code={}&", encode(code));
+        self.send(Route::Pooled, "execute", &params, |token| {
+            self.client
+                .post(&url)
+                .bearer_auth(token)
+                .form(&[("code", code)])
+        })
+        .await
+    }
+
+    /// Returns the id of the user the token belongs to, only works with user tokens.
+    pub async fn get_user_id(&self) -> Result<i64> {
+        let response = self.post(&self.base_url, "users.get", b"", ()).await?;
+        response["response"][0]["id"]
+            .as_i64()
+            .ok_or_else(|| Error::UnexpectedResponse(response.to_string()))
+    }
+
+    pub(crate) async fn get_user_long_poll_server(&self) -> Result<LongPollSession> {
+        let response = parse_response!(
+            self.post(
+                &self.base_url,
+                "messages.getLongPollServer",
+                b"lp_version=3&",
+                ()
+            )
+            .await?,
+            LongPollSession
+        )?;
+
+        Ok(response)
+    }
+
+    /// Same as [`build_long_poll_request`](Self::build_long_poll_request), but for
+    /// [User Long Poll](https://dev.vk.com/en/api/user-long-poll/getting-started).
+    ///
+    /// Returns the response along with the `ts` it was requested with.
+    pub async fn build_user_long_poll_request(&self) -> Result<(UserLongPollResponse, String)> {
+        let mut prev_ts = self._ts.lock().await;
+
+        let mut session_guard = self._session.lock().await;
+        if session_guard.is_none() {
+            let new_session = self.get_user_long_poll_server().await?;
+            *session_guard = Some(new_session);
+        }
+
+        let longpoll = session_guard.as_ref().unwrap();
+        let ts = prev_ts.clone().unwrap_or_else(|| longpoll.ts.clone());
+
+        // Attachments, extended events, platform of online friends and random_id
+        let query = format!(
+            "act=a_check&key={}&ts={}&wait={}&mode=202&version=3&",
+            longpoll.key, ts, self.wait
+        );
+
+        // Unlike Bots Long Poll, the server comes without a scheme
+        let server = if longpoll.server.starts_with("http") {
+            longpoll.server.clone()
+        } else {
+            format!("https://{}", longpoll.server)
+        };
+
+        let response = self.post(&server, "", query.as_bytes(), ()).await?;
+
+        let mut response = parse_response!(response, UserLongPollResponse)?;
+
+        if let Some(new_ts) = response.ts.take() {
+            *prev_ts = Some(new_ts);
+        }
+
+        match response.failed {
+            Some(1) => Err(Error::EventsOutdated {
+                new_ts: prev_ts.clone().unwrap_or_default(),
+            }),
+            Some(2) => Err(Error::KeyExpired),
+            Some(3) => Err(Error::InformationLost),
+            _ => Ok((response, ts)),
+        }
+    }
+
     request!(post);
     request!(get);
 }
+
+/// Which token a request is sent with and whether it is rate limited.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Route {
+    /// Long poll server, doesn't count towards the limit.
+    LongPoll,
+    /// API method bound to the main token, e.g. the ones that set up long poll.
+    Main,
+    /// API method that can be sent with any token of the [`TokenPool`].
+    Pooled,
+}
+
+fn encode_query(query: &[u8]) -> String {
+    query_params(query_str(query)).fold(String::new(), |mut query, (key, value)| {
+        query.push_str(&encode(key));
+        query.push('=');
+        query.push_str(&encode(&value));
+        query.push('&');
+        query
+    })
+}
+
+/// Splits `a=1&b=2&` into its parameters, values escaped by [`Write::arg`](crate::api::Write::arg)
+/// are decoded back.
+pub(crate) fn query_params(query: &str) -> impl Iterator<Item = (&str, Cow<'_, str>)> {
+    query
+        .split('&')
+        .filter(|param| !param.is_empty())
+        .map(|param| {
+            let (key, value) = param.split_once('=').unwrap_or((param, ""));
+            (key, decode(value).unwrap_or(Cow::Borrowed(value)))
+        })
+}
+
+fn query_str(query: &[u8]) -> &str {
+    // This is totally fine!!! "itoa" library guarantee that it will return valid utf8,
+    // hence it's safe to use "unsafe" block here!!! It will make this code blazingly fast!
+    #[cfg(feature = "unsafe")]
+    let query = unsafe { std::str::from_utf8_unchecked(query) };
+
+    #[cfg(not(feature = "unsafe"))]
+    let query = std::str::from_utf8(query).unwrap();
+
+    query
+}
+
+impl From<&str> for RequestBuilder {
+    fn from(access_token: &str) -> Self {
+        Self::new(access_token)
+    }
+}
+
+impl From<&String> for RequestBuilder {
+    fn from(access_token: &String) -> Self {
+        Self::new(access_token)
+    }
+}
+
+impl From<String> for RequestBuilder {
+    fn from(access_token: String) -> Self {
+        Self::new(access_token)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::Write;
+
+    #[derive(Default)]
+    struct Query(Vec<u8>);
+
+    impl Write for Query {
+        fn write(&mut self, arg: &[u8]) {
+            self.0.extend_from_slice(arg);
+        }
+    }
+
+    const VALUES: &[&str] = &[
+        "a&b=c",
+        "1+1 = 2",
+        "100% %41",
+        "привет 👋",
+        "line\nbreak\r\n",
+        "",
+    ];
+
+    fn params(query: &str) -> Vec<(String, String)> {
+        query_params(query)
+            .map(|(key, value)| (key.to_owned(), value.into_owned()))
+            .collect()
+    }
+
+    fn written(value: &str) -> Query {
+        let mut query = Query::default();
+        query.arg("message", value).arg("peer_id", 1);
+        query
+    }
+
+    #[test]
+    fn values_round_trip_through_encode_query() {
+        for value in VALUES {
+            let encoded = encode_query(&written(value).0);
+
+            assert_eq!(
+                params(&encoded),
+                [
+                    ("message".to_owned(), value.to_string()),
+                    ("peer_id".to_owned(), "1".to_owned())
+                ],
+                "{encoded}"
+            );
+        }
+    }
+
+    #[test]
+    fn values_are_decoded_from_a_written_query() {
+        for value in VALUES {
+            let query = written(value);
+
+            assert_eq!(params(query_str(&query.0))[0].1, *value);
+        }
+    }
+
+    #[test]
+    fn encoded_values_keep_separators_escaped() {
+        let encoded = encode_query(&written("a&b=c+d").0);
+
+        assert_eq!(encoded, "message=a%26b%3Dc%2Bd&peer_id=1&");
+    }
+
+    #[test]
+    fn params_without_values() {
+        assert_eq!(
+            params("a&b=&c=1&"),
+            [
+                ("a".to_owned(), String::new()),
+                ("b".to_owned(), String::new()),
+                ("c".to_owned(), "1".to_owned())
+            ]
+        );
+    }
+}
@@ -298,7 +298,7 @@ pub struct User {
     /// Possible values:
     /// - `1` if profile is hidden from search sites
     /// - `0` if profile is available to search sites. (IN privacy settings: https://vk.com/settings?act=privacy,
-    /// in the item “Who can see my page on the Internet", the value “Everyone” is selected
+    ///   in the item “Who can see my page on the Internet", the value “Everyone” is selected
     ///
     ///
     /// Optional fields L-R
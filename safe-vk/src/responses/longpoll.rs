@@ -1,4 +1,5 @@
-use serde::Deserialize;
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::Value;
 
 /// Represents the session data needed to connect to the Long Poll server
 #[derive(Deserialize, Clone, Debug)]
@@ -8,6 +9,7 @@ pub struct LongPollSession {
     /// The server URL to connect to for receiving updates.
     pub server: String,
     /// The ID of the last event received. Used to fetch subsequent events.
+    #[serde(deserialize_with = "string_or_number")]
     pub ts: String,
 }
 
@@ -26,7 +28,7 @@ pub struct LongPollResponse<T> {
 }
 
 /// Represents an individual event in the Long Poll response.
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Event<T> {
     /// The type of event (e.g., message_new, group_join).
     #[serde(rename = "type")]
@@ -40,3 +42,38 @@ pub struct Event<T> {
     // /// The ID of the community where the event occurred.
     // pub object_id: i64,
 }
+
+/// Represents a response from the VK [User Long Poll](https://dev.vk.com/en/api/user-long-poll/getting-started)
+/// server, where every update is an array, see [`UserEvent`](super::UserEvent).
+#[derive(Debug, Deserialize, Clone)]
+pub struct UserLongPollResponse {
+    /// The ID of the last event received. Used in the next request to fetch subsequent events.
+    #[serde(default, deserialize_with = "option_string_or_number")]
+    pub ts: Option<String>,
+    /// A list of events that have occurred.
+    pub updates: Option<Vec<Vec<Value>>>,
+    /// An error code indicating that the session needs to be refreshed or restarted.
+    pub failed: Option<i32>,
+}
+
+/// User Long Poll returns `ts` as a number, while Bots Long Poll returns it as a string.
+fn string_or_number<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    match Value::deserialize(deserializer)? {
+        Value::String(ts) => Ok(ts),
+        Value::Number(ts) => Ok(ts.to_string()),
+        other => Err(serde::de::Error::custom(format!(
+            "expected `ts` to be a string or a number, got {other}"
+        ))),
+    }
+}
+
+fn option_string_or_number<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<String>, D::Error> {
+    match Value::deserialize(deserializer)? {
+        Value::Null => Ok(None),
+        value => string_or_number(value)
+            .map(Some)
+            .map_err(serde::de::Error::custom),
+    }
+}
@@ -3,10 +3,12 @@ mod button;
 mod longpoll;
 mod member;
 mod message;
+mod user_longpoll;
 
 pub mod events;
 
 pub use button::*;
-pub use longpoll::{Event, LongPollResponse, LongPollSession};
+pub use longpoll::{Event, LongPollResponse, LongPollSession, UserLongPollResponse};
 pub use member::*;
 pub use message::*;
+pub use user_longpoll::*;
@@ -0,0 +1,364 @@
+use super::Event;
+use crate::VERSION;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+/// Message flag set for outgoing messages.
+const OUTBOX: i64 = 2;
+/// Message flag set for messages marked as important.
+const IMPORTANT: i64 = 8;
+
+/// An update from [User Long Poll](https://dev.vk.com/en/api/user-long-poll/getting-started),
+/// decoded from its array representation.
+///
+/// Before reaching the router every event is converted into a regular [`Event`], whose
+/// `type` is returned by [`UserEvent::update_type`]. Messages are converted into the same
+/// shape Bots Long Poll uses, so commands and [`Ctx<Message>`](crate::extract::Ctx) work
+/// with user tokens as well, other events carry their payload as the `object`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum UserEvent {
+    /// `4`, a new message was sent or received.
+    MessageNew(UserMessage),
+    /// `5` and `18`, a message was edited.
+    MessageEdit(UserMessage),
+    /// `6`, incoming messages up to `local_id` were read.
+    ReadIn(ReadState),
+    /// `7`, outgoing messages up to `local_id` were read by the other side.
+    ReadOut(ReadState),
+    /// `8`, a friend went online.
+    FriendOnline(FriendStatus),
+    /// `9`, a friend went offline.
+    FriendOffline(FriendStatus),
+    /// `63`, users are typing in a conversation.
+    Typing(Typing),
+    /// Any other event, kept as is.
+    Other(Vec<Value>),
+}
+
+/// A message from User Long Poll.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct UserMessage {
+    /// Identifier of the message.
+    pub id: i64,
+    /// Message flags, see [official documentation](https://dev.vk.com/en/api/user-long-poll/getting-started#Message%20flags).
+    pub flags: i64,
+    /// Destination identifier.
+    pub peer_id: i64,
+    /// Sender identifier.
+    pub from_id: i64,
+    /// Time the message was sent in Unixtime.
+    pub date: i64,
+    /// Message text.
+    pub text: String,
+    /// Identifier used when sending a message.
+    pub random_id: i64,
+    /// Unique automatically increasing number for all messages with this peer.
+    pub conversation_message_id: i64,
+    /// Title of the conversation, only for chats.
+    pub title: Option<String>,
+    /// Service field for bot messages (payload).
+    pub payload: Option<String>,
+    /// Media attachments in the User Long Poll format, e.g. `attach1_type`.
+    pub attachments: Value,
+}
+
+/// Payload of [`UserEvent::ReadIn`] and [`UserEvent::ReadOut`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReadState {
+    pub peer_id: i64,
+    pub local_id: i64,
+}
+
+/// Payload of [`UserEvent::FriendOnline`] and [`UserEvent::FriendOffline`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FriendStatus {
+    pub user_id: i64,
+    /// Platform for [`UserEvent::FriendOnline`], `1` if the friend went offline by timeout
+    /// for [`UserEvent::FriendOffline`].
+    pub extra: i64,
+    pub timestamp: i64,
+}
+
+/// Payload of [`UserEvent::Typing`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Typing {
+    pub peer_id: i64,
+    pub user_ids: Vec<i64>,
+}
+
+impl UserEvent {
+    /// Decodes an event array, `user_id` is the owner of the token and is used as the
+    /// sender of outgoing messages.
+    pub fn from_array(event: &[Value], user_id: i64) -> Self {
+        let int = |i: usize| event.get(i).and_then(Value::as_i64);
+
+        let decoded = match int(0) {
+            Some(4) => UserMessage::from_array(event, user_id).map(UserEvent::MessageNew),
+            Some(5 | 18) => UserMessage::from_array(event, user_id).map(UserEvent::MessageEdit),
+            Some(code @ (6 | 7)) => (|| {
+                let state = ReadState {
+                    peer_id: int(1)?,
+                    local_id: int(2)?,
+                };
+                Some(if code == 6 {
+                    UserEvent::ReadIn(state)
+                } else {
+                    UserEvent::ReadOut(state)
+                })
+            })(),
+            Some(code @ (8 | 9)) => (|| {
+                let status = FriendStatus {
+                    user_id: int(1)?.checked_neg()?,
+                    extra: int(2).unwrap_or_default(),
+                    timestamp: int(3).unwrap_or_default(),
+                };
+                Some(if code == 8 {
+                    UserEvent::FriendOnline(status)
+                } else {
+                    UserEvent::FriendOffline(status)
+                })
+            })(),
+            Some(63) => (|| {
+                Some(UserEvent::Typing(Typing {
+                    peer_id: int(1)?,
+                    user_ids: event
+                        .get(2)?
+                        .as_array()?
+                        .iter()
+                        .filter_map(Value::as_i64)
+                        .collect(),
+                }))
+            })(),
+            _ => None,
+        };
+
+        decoded.unwrap_or_else(|| UserEvent::Other(event.to_vec()))
+    }
+
+    /// The `type` of the [`Event`] this event is converted into.
+    pub fn update_type(&self) -> &'static str {
+        match self {
+            UserEvent::MessageNew(message) if message.flags & OUTBOX != 0 => "message_reply",
+            UserEvent::MessageNew(_) => "message_new",
+            UserEvent::MessageEdit(_) => "message_edit",
+            UserEvent::ReadIn(_) => "message_read_in",
+            UserEvent::ReadOut(_) => "message_read_out",
+            UserEvent::FriendOnline(_) => "friend_online",
+            UserEvent::FriendOffline(_) => "friend_offline",
+            UserEvent::Typing(_) => "message_typing_state",
+            UserEvent::Other(_) => "user_event",
+        }
+    }
+
+    /// Converts the event into an [`Event`] the router understands.
+    pub fn into_event(self, event_id: String) -> Event<Value> {
+        let update_type = self.update_type().to_owned();
+        let object = match self {
+            UserEvent::MessageNew(message) | UserEvent::MessageEdit(message) => json!({
+                "message": message.to_bots_format(),
+                "client_info": null,
+            }),
+            UserEvent::ReadIn(state) | UserEvent::ReadOut(state) => json!(state),
+            UserEvent::FriendOnline(status) | UserEvent::FriendOffline(status) => json!(status),
+            UserEvent::Typing(typing) => json!(typing),
+            UserEvent::Other(event) => Value::Array(event),
+        };
+
+        Event {
+            update_type,
+            event_id,
+            v: VERSION.to_owned(),
+            object,
+        }
+    }
+}
+
+impl UserMessage {
+    /// `[code, message_id, flags, peer_id, timestamp, text, extra, attachments, random_id,
+    /// conversation_message_id, ...]`
+    fn from_array(event: &[Value], user_id: i64) -> Option<Self> {
+        let int = |i: usize| event.get(i).and_then(Value::as_i64);
+        let extra = event.get(6);
+        let extra_str = |key: &str| extra?.get(key)?.as_str().map(str::to_owned);
+
+        let flags = int(2)?;
+        let peer_id = int(3)?;
+        // Chats tell who the sender is, in direct messages it is either the peer or us
+        let from_id = match extra_str("from").and_then(|from| from.parse().ok()) {
+            Some(from_id) => from_id,
+            None if flags & OUTBOX != 0 => user_id,
+            None => peer_id,
+        };
+
+        Some(Self {
+            id: int(1)?,
+            flags,
+            peer_id,
+            from_id,
+            date: int(4).unwrap_or_default(),
+            text: unescape(event.get(5).and_then(Value::as_str).unwrap_or_default()),
+            random_id: int(8).unwrap_or_default(),
+            conversation_message_id: int(9).unwrap_or_default(),
+            title: extra_str("title").filter(|title| !title.trim().is_empty()),
+            payload: extra_str("payload"),
+            attachments: event.get(7).cloned().unwrap_or_else(|| json!({})),
+        })
+    }
+
+    /// The message in the format of [`PersonalMessage`](super::PersonalMessage).
+    fn to_bots_format(&self) -> Value {
+        json!({
+            "id": self.id,
+            "date": self.date,
+            "peer_id": self.peer_id,
+            "from_id": self.from_id,
+            "text": self.text,
+            "random_id": self.random_id,
+            "attachments": [],
+            "important": self.flags & IMPORTANT != 0,
+            "payload": self.payload,
+            "fwd_messages": [],
+            "conversation_message_id": self.conversation_message_id,
+            "out": i64::from(self.flags & OUTBOX != 0),
+        })
+    }
+}
+
+/// User Long Poll sends message text as HTML.
+fn unescape(text: &str) -> String {
+    text.replace("<br>", "\n")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#039;", "'")
+        .replace("&amp;", "&")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode(event: Value) -> UserEvent {
+        UserEvent::from_array(event.as_array().unwrap(), 100)
+    }
+
+    #[test]
+    fn decodes_incoming_message() {
+        let event = decode(json!([
+            4,
+            501,
+            1,
+            2000000001,
+            1700000000,
+            "a &lt;b&gt;<br>&amp; c",
+            {"title": "Chat", "from": "7", "payload": "{\"a\":1}"},
+            {"attach1_type": "photo"},
+            42,
+            12
+        ]));
+
+        let UserEvent::MessageNew(message) = &event else {
+            panic!("not a new message: {event:?}");
+        };
+        assert_eq!(event.update_type(), "message_new");
+        assert_eq!(message.id, 501);
+        assert_eq!(message.peer_id, 2000000001);
+        assert_eq!(message.from_id, 7);
+        assert_eq!(message.date, 1700000000);
+        assert_eq!(message.text, "a <b>\n& c");
+        assert_eq!(message.title.as_deref(), Some("Chat"));
+        assert_eq!(message.payload.as_deref(), Some("{\"a\":1}"));
+        assert_eq!(message.random_id, 42);
+        assert_eq!(message.conversation_message_id, 12);
+        assert_eq!(message.attachments, json!({"attach1_type": "photo"}));
+    }
+
+    #[test]
+    fn direct_message_sender_depends_on_direction() {
+        let incoming = decode(json!([4, 1, 1, 5, 0, "hi", {}, {}]));
+        let outgoing = decode(json!([4, 2, OUTBOX | IMPORTANT, 5, 0, "hi", {}, {}]));
+
+        let (UserEvent::MessageNew(incoming), UserEvent::MessageNew(outgoing)) =
+            (&incoming, &outgoing)
+        else {
+            panic!("not new messages: {incoming:?}, {outgoing:?}");
+        };
+        assert_eq!(incoming.from_id, 5);
+        assert_eq!(outgoing.from_id, 100);
+        assert_eq!(
+            UserEvent::MessageNew(outgoing.clone()).update_type(),
+            "message_reply"
+        );
+
+        let object = UserEvent::MessageNew(outgoing.clone())
+            .into_event("1".to_owned())
+            .object;
+        assert_eq!(object["message"]["out"], 1);
+        assert_eq!(object["message"]["important"], true);
+    }
+
+    #[test]
+    fn decodes_other_events() {
+        assert_eq!(
+            decode(json!([18, 3, 0, 5, 0, "edited", {}, {}])).update_type(),
+            "message_edit"
+        );
+        assert_eq!(
+            decode(json!([7, 5, 30])),
+            UserEvent::ReadOut(ReadState {
+                peer_id: 5,
+                local_id: 30
+            })
+        );
+        assert_eq!(
+            decode(json!([8, -9, 7, 1700000000])),
+            UserEvent::FriendOnline(FriendStatus {
+                user_id: 9,
+                extra: 7,
+                timestamp: 1700000000
+            })
+        );
+        assert_eq!(
+            decode(json!([63, 5, [9, 10], 2, 1700000000])),
+            UserEvent::Typing(Typing {
+                peer_id: 5,
+                user_ids: vec![9, 10]
+            })
+        );
+    }
+
+    #[test]
+    fn short_or_malformed_arrays_are_kept_as_is() {
+        let events = [
+            json!([]),
+            json!([4]),
+            json!([4, 1, 1]),
+            json!([4, "1", 1, 5]),
+            json!([6, 5]),
+            json!([8]),
+            json!([9, i64::MIN]),
+            json!([63, 5, "9"]),
+            json!([null, 1, 2]),
+            json!(["4", 1, 1, 5]),
+            json!([80, 3, 0]),
+        ];
+
+        for event in events {
+            let expected = UserEvent::Other(event.as_array().unwrap().clone());
+            assert_eq!(decode(event), expected);
+        }
+    }
+
+    #[test]
+    fn message_without_optional_fields() {
+        let event = decode(json!([4, 1, 0, 5, null, null, {"title": " "}]));
+
+        let UserEvent::MessageNew(message) = event else {
+            panic!("not a new message: {event:?}");
+        };
+        assert_eq!(message.text, "");
+        assert_eq!(message.date, 0);
+        assert_eq!(message.attachments, json!({}));
+        assert_eq!(message.title, None);
+    }
+}
@@ -0,0 +1,142 @@
+use crate::Error;
+use std::{sync::Arc, time::Duration};
+
+/// Decides how long to wait before the next attempt after the long poll request failed.
+///
+/// Only failures that may go away by themselves are retried: the network or VK being
+/// unavailable ([`Error::is_transport`]) and the long poll session going stale. Any other
+/// error, e.g. a revoked token (error 5), fails the same way on every attempt, so the polling
+/// future resolves with it right away.
+///
+/// The delay grows exponentially with every failed attempt in a row, starting at
+/// `initial_delay` and capped at `max_delay`. A random part of it (see [`jitter`](Self::jitter))
+/// is subtracted, so a lot of bots restarting at once don't hammer VK at the same moment.
+///
+/// ```ignore
+/// safe_vk::start_polling(&token, bot)
+///     .retry_policy(
+///         RetryPolicy::new()
+///             .initial_delay(Duration::from_millis(500))
+///             .max_delay(Duration::from_secs(30))
+///             .max_retries(10),
+///     )
+///     .await
+///     .unwrap();
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetryPolicy {
+    initial_delay: Duration,
+    max_delay: Duration,
+    multiplier: f64,
+    jitter: f64,
+    max_retries: Option<u32>,
+    degraded_after: u32,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            initial_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(60),
+            multiplier: 2.0,
+            jitter: 0.5,
+            max_retries: None,
+            degraded_after: 5,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Retries forever, starting at 1 second and doubling the delay up to 60 seconds.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Delay before the first retry.
+    pub fn initial_delay(mut self, delay: Duration) -> Self {
+        self.initial_delay = delay;
+        self
+    }
+
+    /// The delay never grows beyond this value.
+    pub fn max_delay(mut self, delay: Duration) -> Self {
+        self.max_delay = delay;
+        self
+    }
+
+    /// How many times the delay grows after each failed attempt, `2.0` by default.
+    pub fn multiplier(mut self, multiplier: f64) -> Self {
+        self.multiplier = multiplier.max(1.0);
+        self
+    }
+
+    /// Which part of the delay is random, from `0.0` (none) to `1.0` (anywhere between zero
+    /// and the full delay). Defaults to `0.5`.
+    pub fn jitter(mut self, jitter: f64) -> Self {
+        self.jitter = jitter.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Gives up after `retries` failed attempts in a row, the polling future then resolves
+    /// with the last error. Retries forever by default.
+    pub fn max_retries(mut self, retries: u32) -> Self {
+        self.max_retries = Some(retries);
+        self
+    }
+
+    /// After how many failed attempts in a row the connection is reported as
+    /// [`ConnectionState::Degraded`], `5` by default.
+    pub fn degraded_after(mut self, attempts: u32) -> Self {
+        self.degraded_after = attempts;
+        self
+    }
+
+    /// Whether another attempt is allowed after `attempt` failed ones.
+    pub(crate) fn should_retry(&self, attempt: u32) -> bool {
+        !matches!(self.max_retries, Some(max) if attempt > max)
+    }
+
+    /// Delay before retrying the `attempt`-th failed request, counting from 1.
+    pub(crate) fn delay(&self, attempt: u32) -> Duration {
+        let exponent = i32::try_from(attempt.saturating_sub(1)).unwrap_or(i32::MAX);
+        let delay = (self.initial_delay.as_secs_f64() * self.multiplier.powi(exponent))
+            .min(self.max_delay.as_secs_f64());
+        let jitter = delay * self.jitter * fastrand::f64();
+
+        Duration::from_secs_f64(delay - jitter)
+    }
+
+    /// The state reported after the `attempt`-th failed request.
+    pub(crate) fn state(&self, attempt: u32, delay: Duration) -> ConnectionState {
+        if attempt >= self.degraded_after {
+            ConnectionState::Degraded { attempt, delay }
+        } else {
+            ConnectionState::Reconnecting { attempt, delay }
+        }
+    }
+}
+
+/// Whether a long poll request that failed with `err` is worth repeating, see [`RetryPolicy`].
+pub(crate) fn is_temporary(err: &Error) -> bool {
+    err.is_transport()
+        || matches!(
+            err,
+            Error::EventsOutdated { .. } | Error::KeyExpired | Error::InformationLost
+        )
+}
+
+/// Health of the connection to the long poll server, see
+/// [`Polling::on_connection_state`](crate::start_polling::Polling::on_connection_state).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionState {
+    /// Long poll requests succeed, reported once polling starts and after every recovery.
+    Connected,
+    /// A request failed, the next attempt is made after `delay`.
+    Reconnecting { attempt: u32, delay: Duration },
+    /// Requests keep failing for a while, either VK or the network is likely down.
+    /// Polling keeps retrying according to the [`RetryPolicy`].
+    Degraded { attempt: u32, delay: Duration },
+}
+
+/// Type-erased observer of [`ConnectionState`] changes.
+pub(crate) type StateHandler = Arc<dyn Fn(ConnectionState) + Send + Sync>;
@@ -1,5 +1,5 @@
 use super::{Handler, RequestBuilder, Route, RouteFuture, SafeVk, Update};
-use crate::service::Service;
+use crate::{extract::Parts, service::Service};
 
 use std::sync::{Arc, Mutex};
 
@@ -65,7 +65,8 @@ where
         request: Arc<RequestBuilder>,
         state: S,
     ) -> RouteFuture {
-        self.into_route(state).call(update, request)
+        self.into_route(state)
+            .call((update, Parts::default()), request)
     }
 }
 
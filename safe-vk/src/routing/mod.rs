@@ -1,4 +1,10 @@
-use std::{fmt, sync::Arc};
+use serde::de::DeserializeOwned;
+use std::{
+    fmt,
+    sync::Arc,
+    task::{Context, Poll},
+};
+use tokio::sync::Semaphore;
 
 pub mod adapter;
 pub mod route;
@@ -8,8 +14,8 @@ pub mod router;
 use self::{
     adapter::RouteAdapter,
     route::Route,
-    route_method::ListenerMethod,
-    router::{Listener, MethodListener},
+    route_method::{ListenerMethod, PayloadMatcher},
+    router::{Listener, MethodListener, Reserved},
 };
 
 use super::{extract::Update, service::RouteFuture, Filter};
@@ -18,8 +24,21 @@ use crate::{handler::Handler, RequestBuilder};
 #[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub(crate) struct ListenerId(u32);
 
+/// What the route an update was routed to matched in it. The router hands it to the
+/// extractors of the handler in [`Parts`](crate::extract::Parts).
+#[derive(Debug, Clone, Default)]
+pub(crate) struct RouteMatch {
+    /// Trigger of the command, for usage messages.
+    pub(crate) command: Option<String>,
+    /// Text after the trigger or the regex match, see [`Args`](crate::extract::Args).
+    pub(crate) rest: Option<String>,
+    /// Named groups of a regex command, see [`Captures`](crate::extract::Captures).
+    pub(crate) captures: Vec<(String, String)>,
+}
+
 pub struct SafeVk<S = ()> {
     inner: Arc<SafeVkInner<S>>,
+    reserved: Reserved,
 }
 
 pub struct SafeVkInner<S> {
@@ -28,8 +47,10 @@ pub struct SafeVkInner<S> {
 
 impl<S> Clone for SafeVk<S> {
     fn clone(&self) -> Self {
+        // Reserved permits are never cloned, every clone has to reserve its own
         Self {
             inner: Arc::clone(&self.inner),
+            reserved: Reserved::default(),
         }
     }
 }
@@ -60,6 +81,7 @@ where
             inner: Arc::new(SafeVkInner {
                 method_listener: Default::default(),
             }),
+            reserved: Reserved::default(),
         }
     }
 
@@ -69,6 +91,7 @@ where
     {
         SafeVk {
             inner: Arc::new(f(self.into_inner())),
+            reserved: Reserved::default(),
         }
     }
 
@@ -80,6 +103,7 @@ where
         f(&mut inner);
         SafeVk {
             inner: Arc::new(inner),
+            reserved: Reserved::default(),
         }
     }
 
@@ -107,6 +131,64 @@ where
         })
     }
 
+    /// Routes messages whose text matches the regex `pattern` to `handler`. Named groups of
+    /// the match are available to the handler through the
+    /// [`Captures`](crate::extract::Captures) extractor.
+    ///
+    /// Plain [`command`](Self::command)s are matched first. If several patterns match, the
+    /// one added first wins.
+    ///
+    /// ```ignore
+    /// #[derive(Deserialize)]
+    /// struct Seed {
+    ///     n: u16,
+    /// }
+    ///
+    /// async fn seed(update: Ctx<Message>, Captures(seed): Captures<Seed>) -> Result<()> { .. }
+    ///
+    /// let bot = SafeVk::new().command_regex(r"^/seed (?P<n>\d+)$", seed);
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// If `pattern` isn't a valid regex or a handler was already added for the same pattern.
+    /// Unlike [`merge`](Self::merge) and [`nest`](Self::nest) this happens while the router is
+    /// built, so a typo in the pattern shows up on the first run.
+    pub fn command_regex<H, T>(self, pattern: &str, handler: H) -> Self
+    where
+        H: Handler<T, S> + Sync,
+        T: 'static,
+    {
+        self.tap_inner_mut(|this| {
+            this.method_listener
+                .listen(
+                    MethodListener::new().on(handler),
+                    ListenerMethod::command_regex(pattern),
+                )
+                .unwrap()
+        })
+    }
+
+    /// Routes every update no other route took to `handler`, it's the fallback of the router.
+    /// Several watchers can be added, all of them run in the order they were added. To see
+    /// every update, handled or not, use [`inspect`](Self::inspect).
+    ///
+    /// A handler of any route can decline an update by returning
+    /// [`Error::Continue`](crate::Error::Continue), the update then goes to the next route
+    /// matching it and reaches the watchers if every route declines it.
+    ///
+    /// ```ignore
+    /// async fn admin(update: Ctx<Message>) -> Result<()> {
+    ///     if !is_admin(update.message.from_id) {
+    ///         return Err(Error::Continue);
+    ///     }
+    ///     ..
+    /// }
+    ///
+    /// let bot = SafeVk::new()
+    ///     .command("/ban", admin, Filter::Strict)
+    ///     .watch(moderate);
+    /// ```
     pub fn watch<H, T>(self, handler: H) -> Self
     where
         H: Handler<T, S> + Sync,
@@ -119,6 +201,198 @@ where
         })
     }
 
+    /// Hands every update to `handler`, whether a route took it or not, e.g. for logging.
+    /// Inspectors run after the route or the watchers are done, in the order they were added,
+    /// and can't decline an update.
+    ///
+    /// ```ignore
+    /// let bot = SafeVk::new()
+    ///     .command("/ban", ban, Filter::Strict)
+    ///     .inspect(log);
+    /// ```
+    pub fn inspect<H, T>(self, handler: H) -> Self
+    where
+        H: Handler<T, S> + Sync,
+        T: 'static,
+    {
+        self.tap_inner_mut(|this| {
+            this.method_listener
+                .listen(MethodListener::new().on(handler), ListenerMethod::Inspect)
+                .unwrap()
+        })
+    }
+
+    /// Routes every update of `update_type` to `handler`, e.g. `"group_join"`.
+    ///
+    /// Commands are matched before it, so `on("message_new", ..)` only gets messages that no
+    /// command was found for. Updates of types without a handler still go to
+    /// [`watch`](Self::watch).
+    ///
+    /// ```ignore
+    /// let bot = SafeVk::new()
+    ///     .command("/start", start, Filter::Strict)
+    ///     .on("message_new", fallback)
+    ///     .on("group_leave", goodbye);
+    /// ```
+    pub fn on<H, T>(self, update_type: impl Into<String>, handler: H) -> Self
+    where
+        H: Handler<T, S> + Sync,
+        T: 'static,
+    {
+        self.tap_inner_mut(|this| {
+            this.method_listener
+                .listen(
+                    MethodListener::new().on(handler),
+                    ListenerMethod::Event(update_type.into()),
+                )
+                .unwrap()
+        })
+    }
+
+    /// Routes presses of callback buttons whose payload deserializes into `P` and matches
+    /// `matcher` to `handler`. Pass a value of `P` to match exactly that payload, or a
+    /// [`PayloadMatcher`] to match by a predicate or any payload of the type.
+    ///
+    /// Matched presses don't reach [`on_message_event`](Self::on_message_event), which gets
+    /// the rest. The payload is available to the handler through the
+    /// [`Keyboard<P>`](crate::extract::Keyboard) extractor.
+    ///
+    /// ```ignore
+    /// async fn vote(Keyboard(press): Keyboard<Vote>) -> Result<()> { .. }
+    ///
+    /// let bot = SafeVk::new()
+    ///     .callback(Vote { option: 0 }, cancel)
+    ///     .callback(PayloadMatcher::<Vote>::any(), vote);
+    /// ```
+    pub fn callback<P, H, T>(self, matcher: impl Into<PayloadMatcher<P>>, handler: H) -> Self
+    where
+        P: DeserializeOwned + 'static,
+        H: Handler<T, S> + Sync,
+        T: 'static,
+    {
+        self.tap_inner_mut(|this| {
+            this.method_listener
+                .listen(
+                    MethodListener::new().on(handler),
+                    ListenerMethod::Callback(matcher.into().erase()),
+                )
+                .unwrap()
+        })
+    }
+
+    /// Routes presses of callback buttons (`message_event`) to `handler`.
+    pub fn on_message_event<H, T>(self, handler: H) -> Self
+    where
+        H: Handler<T, S> + Sync,
+        T: 'static,
+    {
+        self.on("message_event", handler)
+    }
+
+    /// Routes edited messages (`message_edit`) to `handler`.
+    pub fn on_message_edit<H, T>(self, handler: H) -> Self
+    where
+        H: Handler<T, S> + Sync,
+        T: 'static,
+    {
+        self.on("message_edit", handler)
+    }
+
+    /// Routes users joining the community (`group_join`) to `handler`.
+    pub fn on_group_join<H, T>(self, handler: H) -> Self
+    where
+        H: Handler<T, S> + Sync,
+        T: 'static,
+    {
+        self.on("group_join", handler)
+    }
+
+    /// Routes new posts on the community wall (`wall_post_new`) to `handler`.
+    pub fn on_wall_post_new<H, T>(self, handler: H) -> Self
+    where
+        H: Handler<T, S> + Sync,
+        T: 'static,
+    {
+        self.on("wall_post_new", handler)
+    }
+
+    /// Adds every route of `other` to this router, after the routes already here. Handy for
+    /// routers built separately, with their state already applied with
+    /// [`with_state`](Self::with_state).
+    ///
+    /// Returns [`Error::RouteCollision`](crate::Error::RouteCollision) if both routers have
+    /// the same route. Commands with the same trigger collide even with different filters,
+    /// watchers and inspectors never collide.
+    ///
+    /// ```ignore
+    /// let games = SafeVk::new()
+    ///     .command("/dice", dice, Filter::Strict)
+    ///     .with_state(scores);
+    ///
+    /// let bot = SafeVk::new()
+    ///     .command("/help", help, Filter::Strict)
+    ///     .merge(games)?;
+    /// ```
+    pub fn merge(self, other: SafeVk<S>) -> crate::Result<Self> {
+        self.combine(other, |method| method)
+    }
+
+    /// Like [`merge`](Self::merge), but commands of `router` are moved under `prefix`:
+    /// `/ban` becomes `/admin ban` when nested under `/admin`. Regex commands lose their
+    /// leading `/` the same way and have to match right after the prefix and whitespace.
+    /// Other routes are added as they are.
+    ///
+    /// ```ignore
+    /// let admin = SafeVk::new()
+    ///     .command("/ban", ban, Filter::Prefix)
+    ///     .command("/mute", mute, Filter::Prefix);
+    ///
+    /// // `/admin ban 123`, `/admin mute 123`
+    /// let bot = SafeVk::new().nest("/admin", admin)?;
+    /// ```
+    pub fn nest(self, prefix: &str, router: SafeVk<S>) -> crate::Result<Self> {
+        self.combine(router, |method| method.nested(prefix))
+    }
+
+    fn combine<F>(self, other: SafeVk<S>, map: F) -> crate::Result<Self>
+    where
+        F: Fn(ListenerMethod) -> ListenerMethod,
+    {
+        let mut inner = self.into_inner();
+        inner
+            .method_listener
+            .merge(other.into_inner().method_listener, map)?;
+
+        Ok(SafeVk {
+            inner: Arc::new(inner),
+            reserved: Reserved::default(),
+        })
+    }
+
+    /// Limits how many handlers of the route added right before this call can run
+    /// at the same time.
+    ///
+    /// A slot of every limited route is reserved before an update is read, so while any of
+    /// them is saturated the bot stops taking new updates, whatever route they are for.
+    ///
+    /// ```ignore
+    /// let bot = SafeVk::new()
+    ///     .command("/g", imagine, Filter::Sensitive)
+    ///     .concurrency_limit(2)
+    ///     .command("/help", help, Filter::Strict);
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// If `max` is zero, since the route could never run.
+    pub fn concurrency_limit(self, max: usize) -> Self {
+        assert!(max > 0, "`concurrency_limit` must be greater than zero");
+        self.tap_inner_mut(|this| {
+            this.method_listener
+                .limit_last(Arc::new(Semaphore::new(max)))
+        })
+    }
+
     pub(crate) fn call_with_state(
         &self,
         update: Update,
@@ -127,7 +401,28 @@ where
     ) -> RouteFuture {
         self.inner
             .method_listener
-            .call_with_state(update, state, request)
+            .call_with_state(update, state, request, Default::default())
+    }
+
+    /// Reserves a slot of every route with a [`concurrency_limit`](Self::concurrency_limit),
+    /// pending while any of them is saturated.
+    pub(crate) fn poll_reserve(&mut self, cx: &mut Context<'_>) -> Poll<crate::Result<()>> {
+        self.inner
+            .method_listener
+            .poll_reserve(&mut self.reserved, cx)
+    }
+
+    /// Routes the `update`, handing the reserved slots over to the matched routes.
+    pub(crate) fn call_reserved(
+        &mut self,
+        update: Update,
+        state: S,
+        request: Arc<RequestBuilder>,
+    ) -> RouteFuture {
+        let reserved = std::mem::take(&mut self.reserved);
+        self.inner
+            .method_listener
+            .call_with_state(update, state, request, reserved)
     }
 
     pub fn with_state<S2>(self, state: S) -> SafeVk<S2> {
@@ -1,5 +1,5 @@
 use crate::{
-    extract::Update,
+    extract::{Parts, Update},
     service::{BoxCloneService, Oneshot, Service, ServiceExt},
     RequestBuilder,
 };
@@ -8,12 +8,12 @@ use std::{
     sync::{Arc, Mutex},
 };
 
-pub struct Route(Mutex<BoxCloneService<Update>>);
+pub struct Route(Mutex<BoxCloneService<(Update, Parts)>>);
 
 impl Route {
     pub(crate) fn new<T>(svc: T) -> Self
     where
-        T: Service<Update, Response = ()> + Clone + Send + 'static,
+        T: Service<(Update, Parts), Response = ()> + Clone + Send + 'static,
         T::Response: 'static,
         T::Future: Send + 'static,
     {
@@ -23,9 +23,14 @@ impl Route {
     pub(crate) fn oneshot_inner(
         &mut self,
         update: Update,
+        parts: Parts,
         request: Arc<RequestBuilder>,
-    ) -> Oneshot<BoxCloneService<Update>, Update> {
-        self.0.get_mut().unwrap().clone().oneshot(update, request)
+    ) -> Oneshot<BoxCloneService<(Update, Parts)>, (Update, Parts)> {
+        self.0
+            .get_mut()
+            .unwrap()
+            .clone()
+            .oneshot((update, parts), request)
     }
 }
 
@@ -1,13 +1,38 @@
-use crate::Filter;
+use super::{RouteMatch, Update};
+use crate::{command_end, Filter};
+use regex::Regex;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use std::{
+    fmt,
+    hash::{Hash, Hasher},
+    marker::PhantomData,
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc,
+    },
+};
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum ListenerMethod {
+    /// Updates no route took, see [`SafeVk::watch`](crate::SafeVk::watch).
     Watch,
+    /// Every update, see [`SafeVk::inspect`](crate::SafeVk::inspect).
+    Inspect,
+    /// Every update of the given `type`.
+    Event(String),
+    /// Presses of callback buttons whose payload matches.
+    Callback(CallbackMatcher),
     Command {
         update_type: &'static str,
         trigger: String,
         filter: Filter,
     },
+    /// Messages whose text matches the regex.
+    CommandRegex {
+        update_type: &'static str,
+        pattern: CommandRegex,
+    },
 }
 
 impl ListenerMethod {
@@ -18,4 +43,301 @@ impl ListenerMethod {
             filter,
         }
     }
+
+    pub(crate) fn command_regex(pattern: &str) -> Self {
+        ListenerMethod::CommandRegex {
+            update_type: "message_new",
+            pattern: CommandRegex::new(pattern),
+        }
+    }
+
+    /// The method with its command moved under `prefix`, see
+    /// [`SafeVk::nest`](crate::SafeVk::nest). Other methods stay as they are.
+    pub(crate) fn nested(self, prefix: &str) -> Self {
+        match self {
+            ListenerMethod::Command {
+                update_type,
+                trigger,
+                filter,
+            } => ListenerMethod::Command {
+                update_type,
+                trigger: format!(
+                    "{prefix} {}",
+                    trigger.trim_start_matches(|c: char| !c.is_alphanumeric())
+                ),
+                filter,
+            },
+            ListenerMethod::CommandRegex {
+                update_type,
+                pattern,
+            } => ListenerMethod::CommandRegex {
+                update_type,
+                pattern: pattern.nested(prefix),
+            },
+            method => method,
+        }
+    }
+
+    /// Whether the two routes can't be in the same router. Commands with the same trigger
+    /// collide whatever their filters are, any number of watchers and inspectors is fine.
+    pub(crate) fn collides(&self, other: &Self) -> bool {
+        match (self, other) {
+            (ListenerMethod::Watch | ListenerMethod::Inspect, _) => false,
+            (
+                ListenerMethod::Command {
+                    update_type,
+                    trigger,
+                    ..
+                },
+                ListenerMethod::Command {
+                    update_type: other_update_type,
+                    trigger: other_trigger,
+                    ..
+                },
+            ) => update_type == other_update_type && trigger == other_trigger,
+            _ => self == other,
+        }
+    }
+
+    /// Where the route is tried among the routes matching an update, lower goes first.
+    /// `None` for watchers and inspectors, which aren't routes.
+    pub(crate) fn tier(&self) -> Option<u8> {
+        match self {
+            ListenerMethod::Command { .. } => Some(0),
+            ListenerMethod::CommandRegex { .. } => Some(1),
+            ListenerMethod::Callback(_) => Some(2),
+            ListenerMethod::Event(_) => Some(3),
+            ListenerMethod::Watch | ListenerMethod::Inspect => None,
+        }
+    }
+
+    /// What the route matched in `event`, or `None` if the route doesn't take it.
+    pub(crate) fn matches(&self, event: &Update) -> Option<RouteMatch> {
+        match self {
+            ListenerMethod::Command {
+                update_type,
+                trigger,
+                filter,
+            } if *update_type == event.update_type => {
+                let text = message_text(event)?;
+                let end = command_end(text, trigger, filter)?;
+                Some(RouteMatch {
+                    command: Some(trigger.clone()),
+                    rest: Some(text[end..].to_owned()),
+                    captures: Vec::new(),
+                })
+            }
+            ListenerMethod::CommandRegex {
+                update_type,
+                pattern,
+            } if *update_type == event.update_type => pattern.route_match(message_text(event)?),
+            ListenerMethod::Callback(matcher)
+                if event.update_type == "message_event"
+                    && event
+                        .object
+                        .get("payload")
+                        .is_some_and(|payload| matcher.matches(payload)) =>
+            {
+                Some(RouteMatch::default())
+            }
+            ListenerMethod::Event(update_type) if *update_type == event.update_type => {
+                Some(RouteMatch::default())
+            }
+            _ => None,
+        }
+    }
+
+    /// How the route is called in traces and metrics.
+    pub(crate) fn route_name(&self) -> &str {
+        match self {
+            ListenerMethod::Watch => "watch",
+            ListenerMethod::Inspect => "inspect",
+            ListenerMethod::Event(update_type) => update_type,
+            ListenerMethod::Callback(matcher) => &matcher.name,
+            ListenerMethod::Command { trigger, .. } => trigger,
+            ListenerMethod::CommandRegex { pattern, .. } => pattern.0.as_str(),
+        }
+    }
+}
+
+/// A compiled [`SafeVk::command_regex`](crate::SafeVk::command_regex) pattern, compared by its
+/// source, since [`Regex`] itself can't be.
+#[derive(Debug, Clone)]
+pub struct CommandRegex(Regex);
+
+impl CommandRegex {
+    /// # Panics
+    ///
+    /// If `pattern` isn't a valid regex.
+    pub(crate) fn new(pattern: &str) -> Self {
+        match Regex::new(pattern) {
+            Ok(regex) => Self(regex),
+            Err(err) => panic!("Invalid command regex `{pattern}`: {err}"),
+        }
+    }
+
+    /// The pattern matched right after `prefix` and whitespace, without its leading `/` like
+    /// nested commands.
+    fn nested(&self, prefix: &str) -> Self {
+        let pattern = self.0.as_str();
+        let pattern = pattern.strip_prefix('^').unwrap_or(pattern);
+        let pattern = pattern.strip_prefix('/').unwrap_or(pattern);
+        Self::new(&format!(r"^{}\s+(?:{pattern})", regex::escape(prefix)))
+    }
+
+    /// Named groups that took part in the match of `text` and the text after the match, or
+    /// `None` if `text` doesn't match.
+    fn route_match(&self, text: &str) -> Option<RouteMatch> {
+        let captures = self.0.captures(text)?;
+
+        // The pattern means nothing to users, so it isn't shown in usage messages
+        Some(RouteMatch {
+            command: None,
+            rest: captures.get(0).map(|whole| text[whole.end()..].to_owned()),
+            captures: self
+                .0
+                .capture_names()
+                .flatten()
+                .filter_map(|name| {
+                    captures
+                        .name(name)
+                        .map(|value| (name.to_owned(), value.as_str().to_owned()))
+                })
+                .collect(),
+        })
+    }
+}
+
+impl PartialEq for CommandRegex {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.as_str() == other.0.as_str()
+    }
+}
+
+impl Eq for CommandRegex {}
+
+impl Hash for CommandRegex {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.as_str().hash(state);
+    }
+}
+
+/// Decides which callback button presses a handler added with
+/// [`SafeVk::callback`](crate::SafeVk::callback) receives, by the button payload.
+///
+/// A payload of another shape never matches, so buttons with different payload types can
+/// be routed independently.
+///
+/// ```ignore
+/// #[derive(Serialize, Deserialize, PartialEq)]
+/// struct Vote { option: u8 }
+///
+/// let bot = SafeVk::new()
+///     // Only this exact payload
+///     .callback(Vote { option: 0 }, cancel)
+///     // Payloads satisfying the predicate
+///     .callback(PayloadMatcher::when(|vote: &Vote| vote.option > 3), too_big)
+///     // Any other `Vote`
+///     .callback(PayloadMatcher::<Vote>::any(), vote);
+/// ```
+pub struct PayloadMatcher<P> {
+    predicate: Option<Arc<dyn Fn(&P) -> bool + Send + Sync>>,
+    _marker: PhantomData<fn() -> P>,
+}
+
+impl<P> PayloadMatcher<P>
+where
+    P: DeserializeOwned + 'static,
+{
+    /// Matches every payload that can be deserialized into `P`.
+    pub fn any() -> Self {
+        Self {
+            predicate: None,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Matches payloads of type `P` for which `predicate` returns `true`.
+    pub fn when<F>(predicate: F) -> Self
+    where
+        F: Fn(&P) -> bool + Send + Sync + 'static,
+    {
+        Self {
+            predicate: Some(Arc::new(predicate)),
+            _marker: PhantomData,
+        }
+    }
+
+    pub(crate) fn erase(self) -> CallbackMatcher {
+        static NEXT_ID: AtomicU32 = AtomicU32::new(0);
+
+        let predicate = self.predicate;
+        CallbackMatcher {
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+            name: format!("callback:{}", std::any::type_name::<P>()),
+            matches: Arc::new(move |payload| match P::deserialize(payload) {
+                Ok(payload) => predicate.as_ref().is_none_or(|f| f(&payload)),
+                Err(_) => false,
+            }),
+        }
+    }
+}
+
+/// Matches the exact payload.
+impl<P> From<P> for PayloadMatcher<P>
+where
+    P: DeserializeOwned + PartialEq + Send + Sync + 'static,
+{
+    fn from(value: P) -> Self {
+        Self::when(move |payload| *payload == value)
+    }
+}
+
+impl<P> fmt::Debug for PayloadMatcher<P> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PayloadMatcher")
+            .field("payload", &std::any::type_name::<P>())
+            .finish()
+    }
+}
+
+/// A [`PayloadMatcher`] with its type erased. Every matcher is a separate route, so two of
+/// them are never equal.
+#[derive(Clone)]
+pub struct CallbackMatcher {
+    id: u32,
+    name: String,
+    matches: Arc<dyn Fn(&Value) -> bool + Send + Sync>,
+}
+
+impl CallbackMatcher {
+    pub(crate) fn matches(&self, payload: &Value) -> bool {
+        (self.matches)(payload)
+    }
+}
+
+impl PartialEq for CallbackMatcher {
+    fn eq(&self, other: &Self) -> bool {
+        self.id == other.id
+    }
+}
+
+impl Eq for CallbackMatcher {}
+
+impl Hash for CallbackMatcher {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.id.hash(state);
+    }
+}
+
+impl fmt::Debug for CallbackMatcher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CallbackMatcher")
+            .field("name", &self.name)
+            .finish()
+    }
+}
+
+fn message_text(event: &Update) -> Option<&str> {
+    event.object.get("message")?.get("text")?.as_str()
 }
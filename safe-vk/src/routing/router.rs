@@ -1,13 +1,22 @@
-use std::{borrow::Cow, collections::HashMap, fmt, sync::Arc};
+use futures_util::{future::BoxFuture, ready};
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    fmt,
+    sync::Arc,
+    task::{Context, Poll},
+};
+use tokio::sync::{AcquireError, OwnedSemaphorePermit, Semaphore};
 
 use super::{
     Handler, ListenerId, ListenerMethod, MethodEndpoint, RequestBuilder, RouteAdapter, RouteFuture,
-    Update,
+    RouteMatch, Update,
 };
-use crate::matchit;
+use crate::{extract::Parts, Error, Response};
 
 pub(super) struct Listener<S> {
-    listeners: HashMap<ListenerId, MethodListener<S>>,
+    // Shared, so the future of an update can get to the routes it falls through to
+    listeners: Arc<HashMap<ListenerId, MethodListener<S>>>,
     node: Arc<Node>,
     prev_listener_id: ListenerId,
 }
@@ -17,6 +26,7 @@ pub struct MethodListener<S = ()> {
     command: MethodEndpoint<S>,
     keyboard: MethodEndpoint<S>,
     any: MethodEndpoint<S>,
+    limit: Option<Arc<Semaphore>>,
 }
 
 impl<S> Listener<S>
@@ -28,11 +38,14 @@ where
         listener: MethodListener<S>,
         method: ListenerMethod,
     ) -> Result<(), Cow<'static, str>> {
-        let endpoint = if let Some((listener_id, method_listener)) = self
-            .node
-            .method_to_listener_id
-            .get(&method)
-            .and_then(|listener_id| {
+        // Every `watch` and `inspect` is a listener of its own, since all of them run
+        let existing = match method {
+            ListenerMethod::Watch | ListenerMethod::Inspect => None,
+            _ => self.node.method_to_listener_id.get(&method),
+        };
+
+        let endpoint = if let Some((listener_id, method_listener)) =
+            existing.and_then(|listener_id| {
                 self.listeners
                     .get(listener_id)
                     .map(|svc| (*listener_id, svc))
@@ -40,7 +53,7 @@ where
             let service = method_listener
                 .clone()
                 .merge_listeners(Some(&method), listener);
-            self.listeners.insert(listener_id, service);
+            Arc::make_mut(&mut self.listeners).insert(listener_id, service);
 
             return Ok(());
         } else {
@@ -49,10 +62,55 @@ where
 
         let id = self.next_listener_id();
         self.set_node(method, id)?;
-        self.listeners.insert(id, endpoint);
+        Arc::make_mut(&mut self.listeners).insert(id, endpoint);
+        Ok(())
+    }
+
+    /// Adds every route of `other` after the routes of this listener, with `map` applied to
+    /// their methods. Nothing is added if any of them is already here.
+    pub(super) fn merge(
+        &mut self,
+        mut other: Listener<S>,
+        map: impl Fn(ListenerMethod) -> ListenerMethod,
+    ) -> Result<(), Error> {
+        let mut ids = other.node.inner.keys().copied().collect::<Vec<_>>();
+        ids.sort_unstable();
+
+        let mut routes: Vec<(ListenerMethod, MethodListener<S>)> = Vec::with_capacity(ids.len());
+        for id in ids {
+            let method = map(other.node.inner[&id].clone());
+            if self
+                .node
+                .inner
+                .values()
+                .any(|route| route.collides(&method))
+                || routes.iter().any(|(added, _)| added.collides(&method))
+            {
+                return Err(Error::RouteCollision(method.route_name().to_owned()));
+            }
+
+            let endpoint = Arc::make_mut(&mut other.listeners)
+                .remove(&id)
+                .expect("no listener for id");
+            routes.push((method, endpoint));
+        }
+
+        for (method, endpoint) in routes {
+            let id = self.next_listener_id();
+            // Setting a node never fails
+            let _ = self.set_node(method, id);
+            Arc::make_mut(&mut self.listeners).insert(id, endpoint);
+        }
         Ok(())
     }
 
+    /// Limits the listener that was added last, see [`SafeVk::concurrency_limit`](super::SafeVk::concurrency_limit).
+    pub(super) fn limit_last(&mut self, semaphore: Arc<Semaphore>) {
+        if let Some(listener) = Arc::make_mut(&mut self.listeners).get_mut(&self.prev_listener_id) {
+            listener.limit = Some(semaphore);
+        }
+    }
+
     fn set_node(&mut self, method: ListenerMethod, id: ListenerId) -> Result<(), String> {
         let mut node =
             Arc::try_unwrap(Arc::clone(&self.node)).unwrap_or_else(|node| (*node).clone());
@@ -61,24 +119,126 @@ where
         Ok(())
     }
 
+    /// Reserves a permit of every limited listener, one after another in the order they
+    /// were added, so routers sharing the limits can't deadlock each other.
+    pub(super) fn poll_reserve(
+        &self,
+        reserved: &mut Reserved,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<(), Error>> {
+        let mut limits = self
+            .listeners
+            .iter()
+            .filter_map(|(id, listener)| Some((*id, listener.limit.as_ref()?)))
+            .filter(|(id, _)| !reserved.permits.contains_key(id))
+            .collect::<Vec<_>>();
+        limits.sort_unstable_by_key(|(id, _)| *id);
+
+        for (id, semaphore) in limits {
+            let acquire = match &mut reserved.acquire {
+                Some((acquiring, acquire)) if *acquiring == id => acquire,
+                acquire => {
+                    let future = Box::pin(Arc::clone(semaphore).acquire_owned());
+                    &mut acquire.insert((id, future)).1
+                }
+            };
+
+            let permit = ready!(acquire.as_mut().poll(cx)).expect("semaphore is never closed");
+            reserved.acquire = None;
+            reserved.permits.insert(id, permit);
+        }
+
+        Poll::Ready(Ok(()))
+    }
+
     pub(super) fn call_with_state(
         &self,
         update: Update,
         state: S,
         request: Arc<RequestBuilder>,
+        mut reserved: Reserved,
     ) -> RouteFuture {
-        match self.node.at(&update) {
-            Ok(id) => {
-                let endpoint = self.listeners.get(&id).expect("no listener for id");
-                endpoint.call_with_state(update, state, request)
+        let first = self
+            .node
+            .find(&update, 0)
+            .map(|(index, id, route)| (index, self.target(id, &mut reserved), route));
+        let inspectors = self
+            .node
+            .inspectors
+            .iter()
+            .map(|id| self.target(*id, &mut reserved))
+            .collect::<Vec<_>>();
+
+        if first.is_none() && self.node.watchers.is_empty() {
+            if let Some(metrics) = request.metrics() {
+                metrics.route_unmatched(&update.update_type);
+            }
+            if inspectors.is_empty() {
+                return RouteFuture::dummy();
             }
-            Err(_) => RouteFuture::dummy(),
         }
+
+        // Slots reserved for routes that won't run are released right away, routes the update
+        // falls through to and watchers wait for their own
+        drop(reserved);
+        let listener = self.clone();
+
+        RouteFuture::boxed(Box::pin(async move {
+            // Routes are tried one by one until one doesn't decline the update
+            let mut next = first;
+            let mut handled = None;
+            while let Some((index, (method, endpoint, permit), route)) = next {
+                let result = call_target(
+                    &method,
+                    &endpoint,
+                    permit,
+                    route,
+                    update.clone(),
+                    &state,
+                    &request,
+                )
+                .await;
+                if !matches!(&result, Err(err) if err.is_continue()) {
+                    handled = Some(result);
+                    break;
+                }
+
+                next = listener
+                    .node
+                    .find(&update, index + 1)
+                    .map(|(index, id, route)| {
+                        (index, listener.target(id, &mut Reserved::default()), route)
+                    });
+            }
+
+            let mut result = match handled {
+                Some(result) => result,
+                // Watchers all run, even if some of them fail
+                None => {
+                    let watchers = listener
+                        .node
+                        .watchers
+                        .iter()
+                        .map(|id| listener.target(*id, &mut Reserved::default()))
+                        .collect::<Vec<_>>();
+                    run_all(watchers, Ok(()), &update, &state, &request).await
+                }
+            };
+
+            // Inspectors run for every update, their errors show up only if nothing else failed
+            result = run_all(inspectors, result, &update, &state, &request).await;
+            result
+        }))
+    }
+
+    fn target(&self, id: ListenerId, reserved: &mut Reserved) -> Target<S> {
+        let method = self.node.inner[&id].clone();
+        let endpoint = self.listeners.get(&id).expect("no listener for id").clone();
+        (method, endpoint, reserved.permits.remove(&id))
     }
 
     pub(super) fn with_state<S2>(self, state: S) -> Listener<S2> {
-        let listeners = self
-            .listeners
+        let listeners = Arc::unwrap_or_clone(self.listeners)
             .into_iter()
             .map(|(id, endpoint)| {
                 let endpoint: MethodListener<S2> = endpoint.with_state(state.clone());
@@ -86,7 +246,7 @@ where
             })
             .collect();
         Listener {
-            listeners,
+            listeners: Arc::new(listeners),
             node: self.node,
             prev_listener_id: self.prev_listener_id,
         }
@@ -112,6 +272,7 @@ where
             command: MethodEndpoint::None,
             keyboard: MethodEndpoint::None,
             any: MethodEndpoint::None,
+            limit: None,
         }
     }
 
@@ -144,6 +305,7 @@ where
             command: self.command.with_state(&state),
             keyboard: self.keyboard.with_state(&state),
             any: self.any.with_state(&state),
+            limit: self.limit,
         }
     }
 
@@ -176,13 +338,42 @@ where
         self.command = merge_inner(method, "COMMAND", self.command, other.command);
         self.keyboard = merge_inner(method, "KEYBOARD", self.keyboard, other.keyboard);
         self.any = merge_inner(method, "ANY", self.any, other.any);
+        self.limit = self.limit.or(other.limit);
 
         self
     }
 
+    /// Calls the endpoint, holding the `permit` reserved for it until the handler is done.
+    /// Without one a limited endpoint waits for a free slot by itself.
     pub(crate) fn call_with_state(
         &self,
         update: Update,
+        parts: Parts,
+        state: S,
+        request: Arc<RequestBuilder>,
+        permit: Option<OwnedSemaphorePermit>,
+    ) -> RouteFuture {
+        let future = self.call_endpoint(update, parts, state, request);
+        match (permit, &self.limit) {
+            (Some(permit), _) => RouteFuture::boxed(Box::pin(async move {
+                let _permit = permit;
+                future.await
+            })),
+            (None, Some(semaphore)) => {
+                let semaphore = Arc::clone(semaphore);
+                RouteFuture::boxed(Box::pin(async move {
+                    let _permit = semaphore.acquire_owned().await;
+                    future.await
+                }))
+            }
+            (None, None) => future,
+        }
+    }
+
+    fn call_endpoint(
+        &self,
+        update: Update,
+        parts: Parts,
         state: S,
         request: Arc<RequestBuilder>,
     ) -> RouteFuture {
@@ -195,11 +386,11 @@ where
                 match $svc {
                     MethodEndpoint::None => {}
                     MethodEndpoint::Route(route) => {
-                        return RouteFuture::new(route.clone().oneshot_inner($upd, $req));
+                        return RouteFuture::new(route.clone().oneshot_inner($upd, parts, $req));
                     }
                     MethodEndpoint::Listener(listener) => {
                         let listener = listener.clone().into_route(state);
-                        return RouteFuture::new(listener.clone().oneshot_inner($upd, $req));
+                        return RouteFuture::new(listener.clone().oneshot_inner($upd, parts, $req));
                     }
                 }
             };
@@ -209,6 +400,7 @@ where
             command,
             keyboard,
             any,
+            limit: _,
         } = self;
 
         call!(update, command, request);
@@ -262,10 +454,27 @@ impl<S: 'static> Clone for MethodListener<S> {
             command: self.command.clone(),
             keyboard: self.keyboard.clone(),
             any: self.any.clone(),
+            limit: self.limit.clone(),
         }
     }
 }
 
+/// Permits of limited listeners reserved by [`SafeVk::poll_ready`](crate::service::Service::poll_ready)
+/// for the next update.
+#[derive(Default)]
+pub(crate) struct Reserved {
+    permits: HashMap<ListenerId, OwnedSemaphorePermit>,
+    acquire: Option<(ListenerId, AcquirePermit)>,
+}
+
+type AcquirePermit = BoxFuture<'static, Result<OwnedSemaphorePermit, AcquireError>>;
+
+type Target<S> = (
+    ListenerMethod,
+    MethodListener<S>,
+    Option<OwnedSemaphorePermit>,
+);
+
 impl<S> Default for MethodListener<S>
 where
     S: Clone,
@@ -280,53 +489,104 @@ struct Node {
     inner: HashMap<ListenerId, ListenerMethod>,
     listener_id_to_method: HashMap<ListenerId, Arc<ListenerMethod>>,
     method_to_listener_id: HashMap<Arc<ListenerMethod>, ListenerId>,
+    /// Routes in the order they are tried: commands go first, then regex commands and
+    /// callback buttons, then handlers of the event type. Routes of the same kind are tried in
+    /// the order they were added.
+    routes: Vec<ListenerId>,
+    /// Every `watch`, in the order they were added.
+    watchers: Vec<ListenerId>,
+    /// Every `inspect`, in the order they were added.
+    inspectors: Vec<ListenerId>,
 }
 
 impl Node {
     pub(crate) fn insert(&mut self, method: ListenerMethod, val: ListenerId) {
+        match method.tier() {
+            Some(tier) => {
+                let index = self
+                    .routes
+                    .partition_point(|id| (self.inner[id].tier(), *id) < (Some(tier), val));
+                self.routes.insert(index, val);
+            }
+            None if method == ListenerMethod::Watch => self.watchers.push(val),
+            None => self.inspectors.push(val),
+        }
+
         let method_arc = Arc::new(method.clone());
-        self.inner.insert(val, method.clone());
+        self.inner.insert(val, method);
         self.listener_id_to_method.insert(val, method_arc.clone());
         self.method_to_listener_id.insert(method_arc, val);
     }
 
-    fn at(&self, event: &Update) -> Result<ListenerId, ()> {
-        let command_listener = self
-            .inner
-            .values()
-            .filter_map(|method| match method {
-                ListenerMethod::Command {
-                    update_type,
-                    trigger,
-                    filter,
-                } => event.object.get("message").and_then(|msg| {
-                    msg.get("text").and_then(|text| {
-                        text.as_str().and_then(|message| {
-                            if *update_type == event.update_type
-                                && matchit(message, trigger, &filter)
-                            {
-                                self.method_to_listener_id.get(method).copied()
-                            } else {
-                                None
-                            }
-                        })
-                    })
-                }),
-                //TODO: Make keyboard as route
-                _ => None,
-            })
-            .next();
+    /// The first route from the `start`th one on that takes the update, with its index and
+    /// what it matched. Later routes aren't looked at.
+    fn find(&self, event: &Update, start: usize) -> Option<(usize, ListenerId, RouteMatch)> {
+        self.routes
+            .iter()
+            .enumerate()
+            .skip(start)
+            .find_map(|(index, id)| Some((index, *id, self.inner[id].matches(event)?)))
+    }
+}
 
-        if let Some(listener_id) = command_listener {
-            Ok(listener_id)
-        } else {
-            self.inner
-                .values()
-                .find_map(|method| match method {
-                    ListenerMethod::Watch => self.method_to_listener_id.get(method).copied(),
-                    _ => None,
-                })
-                .ok_or(())
+/// Runs every target one after another, keeping the first error unless `result` already is
+/// one. Declined updates aren't errors.
+async fn run_all<S>(
+    targets: impl IntoIterator<Item = Target<S>>,
+    mut result: Response<()>,
+    update: &Update,
+    state: &S,
+    request: &Arc<RequestBuilder>,
+) -> Response<()>
+where
+    S: Clone,
+{
+    for (method, endpoint, permit) in targets {
+        let ran = call_target(
+            &method,
+            &endpoint,
+            permit,
+            RouteMatch::default(),
+            update.clone(),
+            state,
+            request,
+        )
+        .await;
+        if let Err(err) = ran {
+            if !err.is_continue() && result.is_ok() {
+                result = Err(err);
+            }
+        }
+    }
+    result
+}
+
+/// Calls the handler of a route, reporting it as the route of the update. Its extractors get
+/// what the route matched with the [`Parts`].
+async fn call_target<S>(
+    method: &ListenerMethod,
+    endpoint: &MethodListener<S>,
+    permit: Option<OwnedSemaphorePermit>,
+    route: RouteMatch,
+    update: Update,
+    state: &S,
+    request: &Arc<RequestBuilder>,
+) -> Response<()>
+where
+    S: Clone,
+{
+    let parts = Parts { route };
+    let route = method.route_name();
+    #[cfg(feature = "tracing")]
+    tracing::Span::current().record("route", route);
+
+    let future =
+        endpoint.call_with_state(update, parts, state.clone(), Arc::clone(request), permit);
+    match request.metrics() {
+        Some(metrics) => {
+            metrics.route_matched(route);
+            future.measured(Arc::clone(metrics), route.to_owned()).await
         }
+        None => future.await,
     }
 }
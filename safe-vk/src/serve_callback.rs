@@ -0,0 +1,469 @@
+//! Receiving updates through the [Callback API](https://dev.vk.com/en/api/callback/getting-started)
+//! instead of Bots Long Poll.
+//!
+//! VK sends every event as a `POST` request with a JSON body to the address configured in the
+//! community settings. The server answers the `confirmation` event with the confirmation code,
+//! rejects events with a wrong `secret`, replies `ok` to everything else and routes the event
+//! into the same [`SafeVk`](crate::SafeVk) router used by [`start_polling`](crate::start_polling()).
+//!
+//! VK sends an event again if it doesn't get `ok` in time, so `ok` is sent as soon as the event
+//! is queued, without waiting for the router to take it.
+//!
+//! Since it is a plain HTTP server, it can be tested locally by posting a recorded event:
+//!
+//! ```shell
+//! $ curl -X POST http://127.0.0.1:8080 -d @event.json
+//! ```
+use crate::{
+    dispatch::{dispatch_options, DispatchOptions, Dispatcher},
+    extract::Update,
+    service::Service,
+    start_polling::SHUTDOWN_TIMEOUT,
+    RequestBuilder, Response,
+};
+use futures_util::future::BoxFuture;
+use hyper::{
+    service::{make_service_fn, service_fn},
+    Body, Method, Request, Server, StatusCode,
+};
+use serde_json::Value;
+use std::{
+    convert::Infallible,
+    future::{Future, IntoFuture},
+    marker::PhantomData,
+    net::SocketAddr,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+    time::Duration,
+};
+use tokio::{
+    sync::{
+        mpsc::{self, UnboundedSender},
+        oneshot,
+    },
+    time::Instant,
+};
+
+/// Event type VK uses to verify the server address.
+const CONFIRMATION: &str = "confirmation";
+
+pub struct Callback<M, S> {
+    request: RequestBuilder,
+    safevk: M,
+    addr: SocketAddr,
+    confirmation: String,
+    secret: Option<String>,
+    shutdown: Option<BoxFuture<'static, ()>>,
+    shutdown_timeout: Duration,
+    options: DispatchOptions,
+    _marker: PhantomData<S>,
+}
+
+/// Starts an HTTP server on `addr` that receives updates from the Callback API.
+///
+/// `confirmation` is the string VK expects in response to the `confirmation` event, it can be
+/// found in the Callback API section of the community settings. `token` has to be a community
+/// access token, the id of the community is requested with it before the server starts.
+///
+/// ```ignore
+/// let bot = SafeVk::new().command("/hello", reply, Filter::Strict);
+///
+/// safe_vk::serve_callback(&token, ([0, 0, 0, 0], 8080), "a1b2c3d4", bot)
+///     .secret("my secret key")
+///     .await
+///     .unwrap();
+/// ```
+pub fn serve_callback<M, S>(
+    token: impl Into<RequestBuilder>,
+    addr: impl Into<SocketAddr>,
+    confirmation: impl Into<String>,
+    safevk: M,
+) -> Callback<M, S>
+where
+    M: Service<Update, Response = ()> + Send + Clone + 'static,
+    <M as Service<Update>>::Future: Send + 'static,
+{
+    Callback {
+        request: token.into(),
+        safevk,
+        addr: addr.into(),
+        confirmation: confirmation.into(),
+        secret: None,
+        shutdown: None,
+        shutdown_timeout: SHUTDOWN_TIMEOUT,
+        options: DispatchOptions::default(),
+        _marker: PhantomData,
+    }
+}
+
+impl<M, S> Callback<M, S> {
+    /// Sets the secret key from the community settings. Events that carry a different
+    /// `secret` field are rejected with `403 Forbidden`.
+    pub fn secret(mut self, secret: impl Into<String>) -> Self {
+        self.secret = Some(secret.into());
+        self
+    }
+
+    /// Stops the server once `signal` resolves.
+    ///
+    /// Events are no longer accepted after the signal. The ones already queued are still
+    /// routed, and together with the running handlers they get
+    /// [`shutdown_timeout`](Self::shutdown_timeout) to finish. Whatever is left after that is
+    /// dropped, and then the server future resolves with `Ok(())`.
+    ///
+    /// ```ignore
+    /// safe_vk::serve_callback(&token, ([0, 0, 0, 0], 8080), "a1b2c3d4", bot)
+    ///     .with_graceful_shutdown(async { tokio::signal::ctrl_c().await.unwrap() })
+    ///     .await
+    ///     .unwrap();
+    /// ```
+    pub fn with_graceful_shutdown<F>(mut self, signal: F) -> Self
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        self.shutdown = Some(Box::pin(signal));
+        self
+    }
+
+    /// Sets how long running handlers may take to finish after a shutdown signal.
+    /// Handlers still running after that are aborted. Defaults to [`SHUTDOWN_TIMEOUT`].
+    pub fn shutdown_timeout(mut self, timeout: Duration) -> Self {
+        self.shutdown_timeout = timeout;
+        self
+    }
+}
+
+dispatch_options!(Callback);
+
+struct Shared {
+    /// Events waiting for the router, taken by a single task in the order they came.
+    updates: UnboundedSender<Update>,
+    confirmation: String,
+    secret: Option<String>,
+}
+
+impl<M, S> IntoFuture for Callback<M, S>
+where
+    M: Service<Update, Response = S> + Send + Clone + 'static,
+    <M as Service<Update>>::Future: Send + 'static,
+    S: Send + Clone + 'static,
+{
+    type Output = Response<()>;
+    type IntoFuture = CallbackFuture;
+
+    fn into_future(self) -> Self::IntoFuture {
+        CallbackFuture(Box::pin(async move {
+            let Self {
+                mut request,
+                safevk,
+                addr,
+                confirmation,
+                secret,
+                shutdown,
+                shutdown_timeout,
+                options,
+                _marker: _,
+            } = self;
+
+            request.set_group_id(request.get_group_id().await?);
+            let mut dispatcher = Dispatcher::new(safevk, Arc::new(request), options);
+
+            let (updates, mut receiver) = mpsc::unbounded_channel();
+            let shared = Arc::new(Shared {
+                updates,
+                confirmation,
+                secret,
+            });
+
+            let make_service = make_service_fn(move |_| {
+                let shared = Arc::clone(&shared);
+                async move {
+                    Ok::<_, Infallible>(service_fn(move |req| {
+                        let shared = Arc::clone(&shared);
+                        async move { Ok::<_, Infallible>(handle(&shared, req).await) }
+                    }))
+                }
+            });
+
+            let shutdown = shutdown.unwrap_or_else(|| Box::pin(std::future::pending()));
+            let (stop, stopped) = oneshot::channel();
+            let server = Server::try_bind(&addr)
+                .map_err(std::io::Error::other)?
+                .serve(make_service)
+                .with_graceful_shutdown(async move {
+                    shutdown.await;
+                    let _ = stop.send(());
+                });
+
+            let dispatch = async move {
+                // Events still queued and running handlers share the time given to finish
+                let deadline = {
+                    // Runs until the server is gone along with every sender of the queue
+                    let routing = async {
+                        while let Some(update) = receiver.recv().await {
+                            let event_id = update.event_id.clone();
+                            if let Err(err) = dispatcher.dispatch(update).await {
+                                error!("Failed to dispatch event {event_id}: {err}");
+                            }
+                        }
+                    };
+                    tokio::pin!(routing);
+
+                    tokio::select! {
+                        () = &mut routing => Instant::now() + shutdown_timeout,
+                        _ = stopped => {
+                            let deadline = Instant::now() + shutdown_timeout;
+                            if tokio::time::timeout_at(deadline, routing).await.is_err() {
+                                warn!("Queued events weren't routed before the shutdown timeout");
+                            }
+                            deadline
+                        }
+                    }
+                };
+                dispatcher
+                    .shutdown(deadline.saturating_duration_since(Instant::now()))
+                    .await;
+            };
+
+            let (served, ()) = tokio::join!(server, dispatch);
+            served.map_err(std::io::Error::other)?;
+
+            Ok(())
+        }))
+    }
+}
+
+async fn handle(shared: &Shared, req: Request<Body>) -> hyper::Response<Body> {
+    if req.method() != Method::POST {
+        return reply(StatusCode::METHOD_NOT_ALLOWED, "method not allowed");
+    }
+
+    let body = match hyper::body::to_bytes(req.into_body()).await {
+        Ok(body) => body,
+        Err(_) => return reply(StatusCode::BAD_REQUEST, "unable to read body"),
+    };
+
+    let json: Value = match serde_json::from_slice(&body) {
+        Ok(json) => json,
+        Err(_) => return reply(StatusCode::BAD_REQUEST, "invalid json"),
+    };
+
+    if let Some(secret) = &shared.secret {
+        if json.get("secret").and_then(Value::as_str) != Some(secret.as_str()) {
+            return reply(StatusCode::FORBIDDEN, "invalid secret");
+        }
+    }
+
+    if json.get("type").and_then(Value::as_str) == Some(CONFIRMATION) {
+        return reply(StatusCode::OK, shared.confirmation.clone());
+    }
+
+    let update: Update = match serde_json::from_value(json) {
+        Ok(update) => update,
+        Err(_) => return reply(StatusCode::BAD_REQUEST, "invalid event"),
+    };
+
+    match shared.updates.send(update) {
+        Ok(()) => reply(StatusCode::OK, "ok"),
+        Err(_) => reply(StatusCode::SERVICE_UNAVAILABLE, "shutting down"),
+    }
+}
+
+fn reply(status: StatusCode, body: impl Into<Body>) -> hyper::Response<Body> {
+    let mut response = hyper::Response::new(body.into());
+    *response.status_mut() = status;
+    response
+}
+
+pub struct CallbackFuture(futures_util::future::BoxFuture<'static, Response<()>>);
+
+impl Future for CallbackFuture {
+    type Output = Response<()>;
+
+    #[inline]
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        self.0.as_mut().poll(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        extract::{Ctx, State},
+        test::MockApi,
+        Filter, SafeVk,
+    };
+    use serde_json::json;
+    use tokio::{
+        sync::{mpsc::UnboundedReceiver, oneshot, Notify},
+        task::JoinHandle,
+    };
+
+    /// Group ids handlers saw, by the text of the message they got.
+    type Seen = UnboundedSender<(String, Option<u64>)>;
+
+    async fn record(update: Ctx<Update>, State(seen): State<Seen>) -> Response<()> {
+        let text = update.object["message"]["text"]
+            .as_str()
+            .unwrap_or_default();
+        let _ = seen.send((text.to_owned(), update.group_id()));
+        Ok(())
+    }
+
+    async fn stuck(State(started): State<Arc<Notify>>) -> Response<()> {
+        started.notify_one();
+        std::future::pending().await
+    }
+
+    fn event(text: &str, secret: &str) -> Value {
+        json!({
+            "type": "message_new",
+            "event_id": format!("event_{text}"),
+            "v": crate::VERSION,
+            "object": { "message": { "text": text, "peer_id": 1, "from_id": 1 } },
+            "group_id": 1,
+            "secret": secret,
+        })
+    }
+
+    /// A free local address, the port is taken back right away for the server to bind.
+    fn free_addr() -> SocketAddr {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.local_addr().unwrap()
+    }
+
+    struct Server {
+        addr: SocketAddr,
+        stop: Option<oneshot::Sender<()>>,
+        served: JoinHandle<Response<()>>,
+    }
+
+    impl Server {
+        fn start<M>(router: M) -> Self
+        where
+            M: Service<Update, Response = ()> + Send + Clone + 'static,
+            <M as Service<Update>>::Future: Send + 'static,
+        {
+            let api = Arc::new(MockApi::default());
+            api.respond_once(
+                "groups.getById",
+                json!({ "response": { "groups": [{ "id": 7 }] } }),
+            );
+            let mut request = RequestBuilder::new("test");
+            request.set_mock(api);
+
+            let addr = free_addr();
+            let (stop, stopped) = oneshot::channel();
+            let callback = serve_callback::<M, ()>(request, addr, "a1b2c3d4", router)
+                .secret("secret")
+                .max_concurrent_handlers(1)
+                .shutdown_timeout(Duration::from_millis(100))
+                .with_graceful_shutdown(async {
+                    let _ = stopped.await;
+                });
+
+            Self {
+                addr,
+                stop: Some(stop),
+                served: tokio::spawn(callback.into_future()),
+            }
+        }
+
+        async fn post(&self, body: &Value) -> (StatusCode, String) {
+            let client = reqwest::Client::new();
+            let url = format!("http://{}", self.addr);
+            for _ in 0..50 {
+                match client.post(&url).json(body).send().await {
+                    Ok(response) => {
+                        let status = StatusCode::from_u16(response.status().as_u16()).unwrap();
+                        return (status, response.text().await.unwrap());
+                    }
+                    // Not listening yet
+                    Err(err) if err.is_connect() => {
+                        tokio::time::sleep(Duration::from_millis(20)).await;
+                    }
+                    Err(err) => panic!("{err}"),
+                }
+            }
+            panic!("server didn't start");
+        }
+
+        async fn stop(mut self) -> Response<()> {
+            let _ = self.stop.take().unwrap().send(());
+            tokio::time::timeout(Duration::from_secs(5), self.served)
+                .await
+                .expect("server didn't stop")
+                .unwrap()
+        }
+    }
+
+    async fn next(seen: &mut UnboundedReceiver<(String, Option<u64>)>) -> (String, Option<u64>) {
+        tokio::time::timeout(Duration::from_secs(5), seen.recv())
+            .await
+            .expect("handler didn't run")
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn answers_confirmation() {
+        let (sender, _seen) = mpsc::unbounded_channel();
+        let server = Server::start(SafeVk::new().watch(record).with_state(sender));
+
+        let confirmation = json!({ "type": "confirmation", "group_id": 7, "secret": "secret" });
+        let answer = server.post(&confirmation).await;
+
+        assert_eq!(answer, (StatusCode::OK, "a1b2c3d4".to_owned()));
+        server.stop().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn rejects_wrong_secret() {
+        let (sender, mut seen) = mpsc::unbounded_channel();
+        let server = Server::start(SafeVk::new().watch(record).with_state(sender));
+
+        let (status, _) = server.post(&event("/hello", "guess")).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        server.stop().await.unwrap();
+
+        assert!(seen.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn routes_events_with_group_id() {
+        let (sender, mut seen) = mpsc::unbounded_channel();
+        let server = Server::start(SafeVk::new().watch(record).with_state(sender));
+
+        let answer = server.post(&event("/hello", "secret")).await;
+
+        assert_eq!(answer, (StatusCode::OK, "ok".to_owned()));
+        assert_eq!(next(&mut seen).await, ("/hello".to_owned(), Some(7)));
+        server.stop().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn answers_ok_while_handlers_are_busy() {
+        let started = Arc::new(Notify::new());
+        let server = Server::start(
+            SafeVk::new()
+                .command("/stuck", stuck, Filter::Strict)
+                .with_state(Arc::clone(&started)),
+        );
+
+        server.post(&event("/stuck", "secret")).await;
+        started.notified().await;
+
+        // The only handler slot is taken, the events wait in the queue
+        for text in ["/stuck", "/again"] {
+            let answer =
+                tokio::time::timeout(Duration::from_secs(1), server.post(&event(text, "secret")))
+                    .await
+                    .expect("reply waited for the handler");
+            assert_eq!(answer, (StatusCode::OK, "ok".to_owned()));
+        }
+
+        // Stuck handlers are aborted once the shutdown timeout is over
+        server.stop().await.unwrap();
+    }
+}
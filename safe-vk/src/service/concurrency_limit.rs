@@ -0,0 +1,105 @@
+use super::Service;
+use crate::{RequestBuilder, Response};
+use futures_util::{future::BoxFuture, ready};
+use pin_project_lite::pin_project;
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+use tokio::sync::{AcquireError, OwnedSemaphorePermit, Semaphore};
+
+/// Limits how many calls of the inner service can be in flight at the same time.
+///
+/// [`poll_ready`](Service::poll_ready) returns [`Poll::Pending`] until a permit is
+/// available, and the permit is held by the response future until it completes.
+pub struct ConcurrencyLimit<T> {
+    inner: T,
+    semaphore: Option<Arc<Semaphore>>,
+    acquire: Option<BoxFuture<'static, Result<OwnedSemaphorePermit, AcquireError>>>,
+    permit: Option<OwnedSemaphorePermit>,
+}
+
+impl<T> ConcurrencyLimit<T> {
+    /// # Panics
+    ///
+    /// If `max` is zero, since no call could ever go through.
+    pub fn new(inner: T, max: usize) -> Self {
+        assert!(max > 0, "concurrency limit must be greater than zero");
+        Self {
+            inner,
+            semaphore: Some(Arc::new(Semaphore::new(max))),
+            acquire: None,
+            permit: None,
+        }
+    }
+
+    /// Doesn't limit anything, used when no limit was configured.
+    pub(crate) fn unlimited(inner: T) -> Self {
+        Self {
+            inner,
+            semaphore: None,
+            acquire: None,
+            permit: None,
+        }
+    }
+}
+
+impl<T, Callback> Service<Callback> for ConcurrencyLimit<T>
+where
+    T: Service<Callback>,
+{
+    type Response = T::Response;
+    type Future = ConcurrencyLimitFuture<T::Future>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Response<()>> {
+        if let (Some(semaphore), None) = (&self.semaphore, &self.permit) {
+            let acquire = self
+                .acquire
+                .get_or_insert_with(|| Box::pin(Arc::clone(semaphore).acquire_owned()));
+
+            let permit = ready!(acquire.as_mut().poll(cx)).expect("semaphore is never closed");
+            self.acquire = None;
+            self.permit = Some(permit);
+        }
+
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, update: Callback, request: Arc<RequestBuilder>) -> Self::Future {
+        ConcurrencyLimitFuture {
+            inner: self.inner.call(update, request),
+            _permit: self.permit.take(),
+        }
+    }
+}
+
+impl<T: Clone> Clone for ConcurrencyLimit<T> {
+    fn clone(&self) -> Self {
+        // Permits are never cloned, every clone has to acquire its own
+        Self {
+            inner: self.inner.clone(),
+            semaphore: self.semaphore.clone(),
+            acquire: None,
+            permit: None,
+        }
+    }
+}
+
+pin_project! {
+    pub struct ConcurrencyLimitFuture<F> {
+        #[pin]
+        inner: F,
+        _permit: Option<OwnedSemaphorePermit>,
+    }
+}
+
+impl<F: Future> Future for ConcurrencyLimitFuture<F> {
+    type Output = F::Output;
+
+    #[inline]
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        self.project().inner.poll(cx)
+    }
+}
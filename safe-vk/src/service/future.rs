@@ -1,21 +1,34 @@
-use super::{BoxCloneService, RequestBuilder, Response, Service, Update};
-use futures_util::ready;
+use super::{BoxCloneService, Parts, RequestBuilder, Response, Service, Update};
+use crate::metrics::Metrics;
+use futures_util::{future::BoxFuture, ready};
 use pin_project_lite::pin_project;
-use std::{future::Future, sync::Arc, task::Poll};
+use std::{future::Future, sync::Arc, task::Poll, time::Instant};
 
 pin_project! {
     pub struct RouteFuture {
         #[pin]
         kind: RouteFutureKind,
+        measured: Option<Measured>,
     }
 }
 
+/// Reports how long the handler of `route` took, counting from the first poll.
+struct Measured {
+    metrics: Arc<dyn Metrics>,
+    route: String,
+    start: Option<Instant>,
+}
+
 pin_project! {
     #[project = RouteFutureKindProj]
     pub enum RouteFutureKind {
         Future {
             #[pin]
-            future: Oneshot<BoxCloneService<Update>, Update>,
+            future: Oneshot<BoxCloneService<(Update, Parts)>, (Update, Parts)>,
+        },
+        Boxed {
+            #[pin]
+            future: BoxFuture<'static, Response<()>>,
         },
         DummyFuture,
     }
@@ -55,16 +68,35 @@ impl<S: Service<Callback>, Callback> State<S, Callback> {
 }
 
 impl RouteFuture {
-    pub(crate) fn new(future: Oneshot<BoxCloneService<Update>, Update>) -> Self {
+    pub(crate) fn new(future: Oneshot<BoxCloneService<(Update, Parts)>, (Update, Parts)>) -> Self {
         Self {
             kind: RouteFutureKind::Future { future },
+            measured: None,
+        }
+    }
+    pub(crate) fn boxed(future: BoxFuture<'static, Response<()>>) -> Self {
+        Self {
+            kind: RouteFutureKind::Boxed { future },
+            measured: None,
         }
     }
+
     pub(crate) fn dummy() -> Self {
         Self {
             kind: RouteFutureKind::DummyFuture,
+            measured: None,
         }
     }
+
+    /// Reports the handler into `metrics` once it finishes.
+    pub(crate) fn measured(mut self, metrics: Arc<dyn Metrics>, route: String) -> Self {
+        self.measured = Some(Measured {
+            metrics,
+            route,
+            start: None,
+        });
+        self
+    }
 }
 
 impl Future for RouteFuture {
@@ -76,14 +108,28 @@ impl Future for RouteFuture {
         cx: &mut std::task::Context<'_>,
     ) -> std::task::Poll<Self::Output> {
         let this = self.project();
-        match this.kind.project() {
-            RouteFutureKindProj::Future { future } => match future.poll(cx) {
-                Poll::Ready(Ok(res)) => Poll::Ready(Ok(res)),
-                Poll::Ready(Err(err)) => Poll::Ready(Err(err)),
-                Poll::Pending => Poll::Pending,
-            },
-            RouteFutureKindProj::DummyFuture => Poll::Ready(Ok(())),
+        if let Some(measured) = this.measured {
+            measured.start.get_or_insert_with(Instant::now);
         }
+
+        let result = match this.kind.project() {
+            RouteFutureKindProj::Future { future } => ready!(future.poll(cx)),
+            RouteFutureKindProj::Boxed { future } => ready!(future.poll(cx)),
+            RouteFutureKindProj::DummyFuture => Ok(()),
+        };
+
+        if let Some(Measured {
+            metrics,
+            route,
+            start,
+        }) = this.measured.take()
+        {
+            let duration = start.map(|start| start.elapsed()).unwrap_or_default();
+            let failed = matches!(&result, Err(err) if !err.is_continue());
+            metrics.handler_finished(&route, duration, failed);
+        }
+
+        Poll::Ready(result)
     }
 }
 
@@ -109,7 +155,7 @@ where
         loop {
             match this.state.as_mut().project() {
                 StateProj::NotReady { svc, upd, req } => {
-                    let _ = ready!(svc.poll_ready(cx))?;
+                    ready!(svc.poll_ready(cx))?;
                     let f = svc.call(
                         upd.take().expect("already called"),
                         req.take().expect("already called"),
@@ -1,5 +1,8 @@
 use crate::{
-    extract::Update, handler::Handler, routing::route::Route, RequestBuilder, Response, SafeVk,
+    extract::{Parts, Update},
+    handler::Handler,
+    routing::route::Route,
+    RequestBuilder, Response, SafeVk,
 };
 use std::{
     future::Future,
@@ -10,10 +13,12 @@ use std::{
 };
 
 mod boxed_clone;
+mod concurrency_limit;
 mod future;
 mod map_future;
 
 pub use boxed_clone::BoxCloneService;
+pub use concurrency_limit::{ConcurrencyLimit, ConcurrencyLimitFuture};
 pub use future::{Oneshot, RouteFuture};
 pub use map_future::MapFuture;
 
@@ -75,12 +80,14 @@ impl Service<Update> for SafeVk<()> {
     type Response = ();
     type Future = RouteFuture;
 
-    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Response<()>> {
-        Poll::Ready(Ok(()))
+    /// Pending while any route with a [`concurrency_limit`](SafeVk::concurrency_limit)
+    /// has no free slot.
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Response<()>> {
+        self.poll_reserve(cx)
     }
 
     fn call(&mut self, update: Update, request: Arc<RequestBuilder>) -> Self::Future {
-        self.call_with_state(update, (), request)
+        self.call_reserved(update, (), request)
     }
 }
 
@@ -100,7 +107,7 @@ impl<H, T, S> HandlerService<H, T, S> {
     }
 }
 
-impl<H, T, S> Service<Update> for HandlerService<H, T, S>
+impl<H, T, S> Service<(Update, Parts)> for HandlerService<H, T, S>
 where
     H: Handler<T, S> + Clone + Send + 'static,
     S: Clone + Send + Sync,
@@ -112,9 +119,13 @@ where
         Poll::Ready(Ok(()))
     }
 
-    fn call(&mut self, update: Update, request: Arc<RequestBuilder>) -> Self::Future {
+    fn call(
+        &mut self,
+        (update, parts): (Update, Parts),
+        request: Arc<RequestBuilder>,
+    ) -> Self::Future {
         let handler = self.handler.clone();
-        let future = Handler::call(handler, update, self.state.clone(), request);
+        let future = Handler::call(handler, update, parts, self.state.clone(), request);
 
         Box::pin(async move { future.await.map(|_| ()) })
     }
@@ -134,7 +145,7 @@ where
     }
 }
 
-impl Service<Update> for Route {
+impl Service<(Update, Parts)> for Route {
     type Response = ();
     type Future = RouteFuture;
 
@@ -144,8 +155,12 @@ impl Service<Update> for Route {
     }
 
     #[inline]
-    fn call(&mut self, update: Update, request: Arc<RequestBuilder>) -> Self::Future {
-        RouteFuture::new(self.oneshot_inner(update, request))
+    fn call(
+        &mut self,
+        (update, parts): (Update, Parts),
+        request: Arc<RequestBuilder>,
+    ) -> Self::Future {
+        RouteFuture::new(self.oneshot_inner(update, parts, request))
     }
 }
 
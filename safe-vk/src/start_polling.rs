@@ -1,35 +1,190 @@
-use crate::{extract::Update, service::Service, Error, RequestBuilder, Response};
+use crate::{
+    checkpoint::{CheckpointStore, Unsaved},
+    dispatch::{dispatch_options, DispatchOptions, Dispatcher},
+    extract::Update,
+    replay::Recorder,
+    responses::{LongPollSession, UserEvent},
+    retry::{is_temporary, ConnectionState, RetryPolicy, StateHandler},
+    service::Service,
+    Error, RequestBuilder, Response,
+};
+use futures_util::future::BoxFuture;
 use std::{
-    future::{poll_fn, Future, IntoFuture},
+    future::{Future, IntoFuture},
     marker::PhantomData,
+    path::PathBuf,
     pin::Pin,
     sync::Arc,
     task::{Context, Poll},
+    time::Duration,
 };
+use tokio::{sync::watch, task::JoinSet};
+
+/// How long [`Polling`] and `serve_callback` wait for running handlers after a shutdown
+/// signal by default.
+pub const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(30);
 
 pub struct Polling<M, S> {
     request: RequestBuilder,
     safevk: M,
+    shutdown: Option<BoxFuture<'static, ()>>,
+    shutdown_timeout: Duration,
+    on_shutdown: Option<Box<dyn FnOnce(Option<String>) -> BoxFuture<'static, ()> + Send>>,
+    options: DispatchOptions,
+    retry_policy: RetryPolicy,
+    on_connection_state: Option<StateHandler>,
+    checkpoint: Option<Box<dyn CheckpointStore>>,
+    record: Option<PathBuf>,
+    source: Source,
     _marker: PhantomData<S>,
 }
 
-pub fn start_polling<M, S>(token: &str, safevk: M) -> Polling<M, S>
+/// Starts receiving updates through [Bots Long Poll](https://dev.vk.com/en/api/bots-long-poll/getting-started)
+/// and routing them into `safevk`.
+///
+/// `token` is either a community access token or a [`RequestBuilder`] configured with
+/// [`RequestBuilder::builder`].
+pub fn start_polling<M, S>(token: impl Into<RequestBuilder>, safevk: M) -> Polling<M, S>
 where
     M: Service<Update, Response = ()> + Send + Clone + 'static,
-    <M as Service<Update>>::Future: Send,
+    <M as Service<Update>>::Future: Send + 'static,
 {
-    let request = RequestBuilder::new(token);
+    polling(token, safevk, Source::Bots)
+}
+
+/// Same as [`start_polling`], but receives updates through
+/// [User Long Poll](https://dev.vk.com/en/api/user-long-poll/getting-started),
+/// so it works with user access tokens. Unless the token was given a
+/// [`rate_limit`](crate::RequestConfig::rate_limit), API calls are limited to
+/// [`RateLimit::user`](crate::RateLimit::user).
+///
+/// Updates are decoded into [`UserEvent`]s and converted into regular events before they
+/// reach the router, so new messages can be handled with the usual commands:
+///
+/// ```ignore
+/// let bot = SafeVk::new().command("/ping", pong, Filter::Strict);
+///
+/// safe_vk::start_user_polling(&user_token, bot).await.unwrap();
+/// ```
+pub fn start_user_polling<M, S>(token: impl Into<RequestBuilder>, safevk: M) -> Polling<M, S>
+where
+    M: Service<Update, Response = ()> + Send + Clone + 'static,
+    <M as Service<Update>>::Future: Send + 'static,
+{
+    polling(token, safevk, Source::User)
+}
+
+fn polling<M, S>(token: impl Into<RequestBuilder>, safevk: M, source: Source) -> Polling<M, S> {
+    let request = token.into();
     Polling {
         request,
         safevk,
+        shutdown: None,
+        shutdown_timeout: SHUTDOWN_TIMEOUT,
+        on_shutdown: None,
+        options: DispatchOptions::default(),
+        retry_policy: RetryPolicy::default(),
+        on_connection_state: None,
+        checkpoint: None,
+        record: None,
+        source,
         _marker: PhantomData,
     }
 }
 
+impl<M, S> Polling<M, S> {
+    /// Stops polling once `signal` resolves.
+    ///
+    /// No new long poll batches are fetched after the signal, handlers that are
+    /// already running get [`shutdown_timeout`](Self::shutdown_timeout) to finish,
+    /// and then the polling future resolves with `Ok(())`.
+    ///
+    /// ```ignore
+    /// safe_vk::start_polling(&token, bot)
+    ///     .with_graceful_shutdown(async { tokio::signal::ctrl_c().await.unwrap() })
+    ///     .await
+    ///     .unwrap();
+    /// ```
+    pub fn with_graceful_shutdown<F>(mut self, signal: F) -> Self
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        self.shutdown = Some(Box::pin(signal));
+        self
+    }
+
+    /// Sets how long running handlers may take to finish after a shutdown signal.
+    /// Handlers still running after that are aborted. Defaults to [`SHUTDOWN_TIMEOUT`].
+    pub fn shutdown_timeout(mut self, timeout: Duration) -> Self {
+        self.shutdown_timeout = timeout;
+        self
+    }
+
+    /// Calls `f` with the last long poll `ts` once polling has been shut down,
+    /// so it can be persisted and used to resume after a restart.
+    pub fn on_shutdown<F, Fut>(mut self, f: F) -> Self
+    where
+        F: FnOnce(Option<String>) -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.on_shutdown = Some(Box::new(move |ts| Box::pin(f(ts))));
+        self
+    }
+
+    /// Sets how failed long poll requests are retried, see [`RetryPolicy`].
+    pub fn retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.retry_policy = policy;
+        self
+    }
+
+    /// Calls `f` whenever the connection to the long poll server changes its state,
+    /// e.g. to expose it in a health check or to alert when VK is unreachable.
+    ///
+    /// ```ignore
+    /// safe_vk::start_polling(&token, bot)
+    ///     .on_connection_state(|state| match state {
+    ///         ConnectionState::Degraded { attempt, .. } => eprintln!("VK is unreachable, attempt {attempt}"),
+    ///         _ => {}
+    ///     })
+    ///     .await
+    ///     .unwrap();
+    /// ```
+    pub fn on_connection_state<F>(mut self, f: F) -> Self
+    where
+        F: Fn(ConnectionState) + Send + Sync + 'static,
+    {
+        self.on_connection_state = Some(Arc::new(f));
+        self
+    }
+
+    /// Resumes polling from the `ts` saved in `store` and saves a new one once every handler
+    /// of a batch is done, see [`checkpoint`](crate::checkpoint) for details.
+    ///
+    /// ```ignore
+    /// safe_vk::start_polling(&token, bot)
+    ///     .checkpoint(FileCheckpoint::new("ts.txt"))
+    ///     .await
+    ///     .unwrap();
+    /// ```
+    pub fn checkpoint(mut self, store: impl CheckpointStore) -> Self {
+        self.checkpoint = Some(Box::new(store));
+        self
+    }
+
+    /// Appends every received update to the JSONL file at `path`, so it can be fed back
+    /// into a router later, see [`replay`](mod@crate::replay).
+    pub fn record(mut self, path: impl Into<PathBuf>) -> Self {
+        self.record = Some(path.into());
+        self
+    }
+}
+
+dispatch_options!(Polling);
+
 impl<M, S> IntoFuture for Polling<M, S>
 where
     M: Service<Update, Response = S> + Send + Clone + 'static,
-    <M as Service<Update>>::Future: Send,
+    <M as Service<Update>>::Future: Send + 'static,
     S: Send + Clone + 'static,
 {
     type Output = Response<()>;
@@ -38,55 +193,395 @@ where
     fn into_future(self) -> Self::IntoFuture {
         PollFuture(Box::pin(async move {
             let Self {
-                request,
-                mut safevk,
+                mut request,
+                safevk,
+                shutdown,
+                shutdown_timeout,
+                on_shutdown,
+                options,
+                retry_policy,
+                on_connection_state,
+                checkpoint,
+                record,
+                source,
                 _marker: _,
             } = self;
 
-            let group_id = request.get_group_id().await?;
+            let mut recorder = match &record {
+                Some(path) => Some(Recorder::open(path).await?),
+                None => None,
+            };
+
+            let mut shutdown = shutdown.unwrap_or_else(|| Box::pin(std::future::pending()));
+            let notify = |state| {
+                if let Some(f) = &on_connection_state {
+                    f(state);
+                }
+            };
+
+            // Failed attempts in a row, reset by the first successful request
+            let mut attempt = 0;
+            let session = loop {
+                let err = match Session::start(source, &request).await {
+                    Ok(session) => break session,
+                    Err(err) => err,
+                };
+
+                attempt += 1;
+                if !is_temporary(&err) || !retry_policy.should_retry(attempt) {
+                    return Err(err);
+                }
+
+                let delay = retry_policy.delay(attempt);
+                warn!("Failed to start long polling, retrying in {delay:?}: {err}");
+                notify(retry_policy.state(attempt, delay));
+
+                tokio::select! {
+                    _ = tokio::time::sleep(delay) => {}
+                    _ = &mut shutdown => {
+                        if let Some(on_shutdown) = on_shutdown {
+                            on_shutdown(request.ts().await).await;
+                        }
+                        return Ok(());
+                    }
+                }
+            };
+            attempt = 0;
+
+            match session {
+                Session::Bots { group_id } => request.set_group_id(group_id),
+                Session::User { .. } => request.set_user_rate_limit(),
+            }
+
+            let mut saved_ts = match &checkpoint {
+                Some(store) => store.load().await?,
+                None => None,
+            };
+            if let Some(ts) = &saved_ts {
+                request.update_ts(ts.clone()).await;
+            }
+            // Only tracked when there is somewhere to save the `ts`
+            let mut unsaved = checkpoint.as_ref().map(|_| Unsaved::default());
+
             let request = Arc::new(request);
+            let mut dispatcher = Dispatcher::new(safevk, Arc::clone(&request), options);
+
+            let reconnect = |reason| {
+                if let Some(metrics) = request.metrics() {
+                    metrics.long_poll_reconnect(reason);
+                }
+            };
+            let mut connected = false;
+            let mut result = Ok(());
 
             loop {
-                match request.build_long_poll_request(group_id).await {
-                    Ok(res) => {
-                        if let Some(updates) = res.updates {
-                            for event in updates {
-                                poll_fn(|cx| safevk.poll_ready(cx)).await.unwrap();
-
-                                let request_clone = Arc::clone(&request);
-                                let mut safevk = safevk.clone();
-
-                                let _: tokio::task::JoinHandle<Response<()>> =
-                                    tokio::spawn(async move {
-                                        match safevk.call(event, request_clone).await {
-                                            Ok(..) => Ok(()),
-                                            Err(err) => panic!("{err}"),
-                                        }
-                                    });
+                // An interrupted request doesn't advance `ts`, so no events are lost here
+                let response = tokio::select! {
+                    response = session.poll(&request) => response,
+                    _ = &mut shutdown => break,
+                };
+
+                let outcome = match response {
+                    Ok(updates) => {
+                        let batch = match (&mut unsaved, request.ts().await) {
+                            (Some(unsaved), Some(ts)) => Some(unsaved.push(ts)),
+                            _ => None,
+                        };
+                        for event in updates {
+                            if let Some(recorder) = &mut recorder {
+                                recorder.record(&event).await;
                             }
+                            dispatcher.dispatch_in(event, batch.as_ref()).await?;
+                        }
+                        Ok(())
+                    }
+                    Err(Error::EventsOutdated { new_ts }) => {
+                        #[cfg(feature = "tracing")]
+                        tracing::info!(
+                            new_ts,
+                            "long poll events are outdated, skipping to the new ts"
+                        );
+                        if let Some(unsaved) = &mut unsaved {
+                            unsaved.push(new_ts.clone());
                         }
+                        request.update_ts(new_ts).await;
+                        Ok(())
                     }
-                    Err(Error::EventsOutdated { new_ts }) => request.update_ts(new_ts).await,
-                    Err(Error::KeyExpired) => match request.get_long_poll_server(group_id).await {
-                        Ok(new_session) => request.update_session(new_session).await,
-                        Err(err) => {
-                            eprintln!("Failed to fetch new long poll server session: {err}");
+                    Err(Error::KeyExpired) => match session.server(&request).await {
+                        Ok(new_session) => {
+                            #[cfg(feature = "tracing")]
+                            tracing::info!("long poll key expired, got a new one");
+                            reconnect("key_expired");
+                            request.update_session(new_session).await;
+                            Ok(())
                         }
+                        Err(err) => Err(err),
                     },
-                    Err(Error::InformationLost) => {
-                        match request.get_long_poll_server(group_id).await {
-                            Ok(new_session) => {
-                                request.update_ts(new_session.ts.clone()).await;
-                                request.update_session(new_session).await;
-                            }
-                            Err(err) => {
-                                eprintln!("Failed to fetch new long poll server session: {err}")
+                    Err(Error::InformationLost) => match session.server(&request).await {
+                        Ok(new_session) => {
+                            #[cfg(feature = "tracing")]
+                            tracing::info!(
+                                ts = new_session.ts,
+                                "long poll information lost, started a new session"
+                            );
+                            reconnect("information_lost");
+                            if let Some(unsaved) = &mut unsaved {
+                                unsaved.push(new_session.ts.clone());
                             }
+                            request.update_ts(new_session.ts.clone()).await;
+                            request.update_session(new_session).await;
+                            Ok(())
+                        }
+                        Err(err) => Err(err),
+                    },
+                    Err(err) => Err(err),
+                };
+
+                match outcome {
+                    Ok(()) => {
+                        attempt = 0;
+                        if !connected {
+                            connected = true;
+                            notify(ConnectionState::Connected);
+                        }
+
+                        if let (Some(store), Some(unsaved)) = (&checkpoint, &mut unsaved) {
+                            save_checkpoint(store.as_ref(), unsaved.take_done(), &mut saved_ts)
+                                .await;
+                        }
+                    }
+                    Err(err) => {
+                        attempt += 1;
+                        connected = false;
+
+                        if !is_temporary(&err) {
+                            error!("Long poll failed, giving up: {err}");
+                            result = Err(err);
+                            break;
+                        }
+                        if !retry_policy.should_retry(attempt) {
+                            error!("Long poll failed {attempt} times in a row, giving up: {err}");
+                            result = Err(err);
+                            break;
+                        }
+
+                        let delay = retry_policy.delay(attempt);
+                        warn!("Long poll failed, retrying in {delay:?}: {err}");
+                        notify(retry_policy.state(attempt, delay));
+                        reconnect("error");
+
+                        tokio::select! {
+                            _ = tokio::time::sleep(delay) => {}
+                            _ = &mut shutdown => break,
                         }
                     }
-                    Err(err) => eprintln!("Error occured: {err}"),
                 }
             }
+
+            dispatcher.shutdown(shutdown_timeout).await;
+
+            // Aborted handlers never finish, so their batches are received again next time
+            if let (Some(store), Some(unsaved)) = (&checkpoint, &mut unsaved) {
+                save_checkpoint(store.as_ref(), unsaved.take_done(), &mut saved_ts).await;
+            }
+
+            if let Some(on_shutdown) = on_shutdown {
+                on_shutdown(request.ts().await).await;
+            }
+
+            result
+        }))
+    }
+}
+
+/// Which long poll API the updates are received from.
+#[derive(Debug, Clone, Copy)]
+enum Source {
+    Bots,
+    User,
+}
+
+/// A [`Source`] along with the id of the token owner, resolved once polling starts.
+enum Session {
+    Bots { group_id: u64 },
+    User { user_id: i64 },
+}
+
+impl Session {
+    async fn start(source: Source, request: &RequestBuilder) -> Response<Self> {
+        Ok(match source {
+            Source::Bots => Session::Bots {
+                group_id: request.get_group_id().await?,
+            },
+            Source::User => Session::User {
+                user_id: request.get_user_id().await?,
+            },
+        })
+    }
+
+    /// Waits for the next batch of updates.
+    async fn poll(&self, request: &RequestBuilder) -> Response<Vec<Update>> {
+        match *self {
+            Session::Bots { group_id } => Ok(request
+                .build_long_poll_request(group_id)
+                .await?
+                .updates
+                .unwrap_or_default()),
+            Session::User { user_id } => {
+                let (response, ts) = request.build_user_long_poll_request().await?;
+                let updates = response.updates.unwrap_or_default();
+
+                // User Long Poll events have no id, but the same `ts` always returns the
+                // same batch, so the position in it is stable enough for deduplication
+                Ok(updates
+                    .iter()
+                    .enumerate()
+                    .map(|(i, event)| {
+                        UserEvent::from_array(event, user_id).into_event(format!("{ts}_{i}"))
+                    })
+                    .collect())
+            }
+        }
+    }
+
+    /// Requests a new long poll session.
+    async fn server(&self, request: &RequestBuilder) -> Response<LongPollSession> {
+        match *self {
+            Session::Bots { group_id } => request.get_long_poll_server(group_id).await,
+            Session::User { .. } => request.get_user_long_poll_server().await,
+        }
+    }
+}
+
+/// Saves `ts` into `store` unless it is the one saved last time.
+async fn save_checkpoint(
+    store: &dyn CheckpointStore,
+    ts: Option<String>,
+    saved_ts: &mut Option<String>,
+) {
+    let Some(ts) = ts else { return };
+    if saved_ts.as_deref() == Some(&ts) {
+        return;
+    }
+
+    match store.save(&ts).await {
+        Ok(()) => *saved_ts = Some(ts),
+        Err(err) => warn!("Failed to save long poll checkpoint: {err}"),
+    }
+}
+
+/// Runs long poll of several communities at once, e.g. to serve all of them from one process.
+///
+/// Every community is polled with its own token, and handlers reply with the token of the
+/// community the update came from. [`Ctx::group_id`](crate::extract::Ctx::group_id) tells
+/// which one it was. Communities can share one router or have their own.
+///
+/// ```ignore
+/// let bot = SafeVk::new().command("/hello", hello, Filter::Strict);
+///
+/// MultiPoller::new()
+///     .add(&first_token, bot.clone())
+///     .add(&second_token, bot)
+///     .add_polling(safe_vk::start_polling(&third_token, other_bot).dispatch_mode(DispatchMode::PerPeer))
+///     .with_graceful_shutdown(async { tokio::signal::ctrl_c().await.unwrap() })
+///     .await
+///     .unwrap();
+/// ```
+///
+/// A community that fails doesn't stop the others. The future resolves once all of them
+/// have stopped, with the first error if there was one.
+#[derive(Default)]
+pub struct MultiPoller {
+    sessions: Vec<Box<dyn FnOnce(BoxFuture<'static, ()>) -> PollFuture + Send>>,
+    shutdown: Option<BoxFuture<'static, ()>>,
+}
+
+impl MultiPoller {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a community whose updates are routed into `safevk`, same as [`start_polling`].
+    pub fn add<M>(self, token: impl Into<RequestBuilder>, safevk: M) -> Self
+    where
+        M: Service<Update, Response = ()> + Send + Clone + 'static,
+        <M as Service<Update>>::Future: Send + 'static,
+    {
+        self.add_polling(start_polling(token, safevk))
+    }
+
+    /// Adds an already configured [`Polling`], e.g. with its own retry policy or checkpoint.
+    ///
+    /// The polling stops on the shutdown signal of the [`MultiPoller`], unless it has one set.
+    pub fn add_polling<M, S>(mut self, polling: Polling<M, S>) -> Self
+    where
+        M: Service<Update, Response = S> + Send + Clone + 'static,
+        <M as Service<Update>>::Future: Send + 'static,
+        S: Send + Clone + 'static,
+    {
+        self.sessions.push(Box::new(move |signal| {
+            let mut polling = polling;
+            polling.shutdown.get_or_insert(signal);
+            polling.into_future()
+        }));
+        self
+    }
+
+    /// Stops polling of every community once `signal` resolves,
+    /// see [`Polling::with_graceful_shutdown`].
+    pub fn with_graceful_shutdown<F>(mut self, signal: F) -> Self
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        self.shutdown = Some(Box::pin(signal));
+        self
+    }
+}
+
+impl IntoFuture for MultiPoller {
+    type Output = Response<()>;
+    type IntoFuture = PollFuture;
+
+    fn into_future(self) -> Self::IntoFuture {
+        PollFuture(Box::pin(async move {
+            let (stop, _) = watch::channel(false);
+            let mut tasks = JoinSet::new();
+
+            for session in self.sessions {
+                let mut stopped = stop.subscribe();
+                let signal = Box::pin(async move {
+                    let _ = stopped.wait_for(|stopped| *stopped).await;
+                });
+                tasks.spawn(session(signal));
+            }
+
+            let mut shutdown = self
+                .shutdown
+                .unwrap_or_else(|| Box::pin(std::future::pending()));
+            let mut stopping = false;
+            let mut result = Ok(());
+
+            loop {
+                tokio::select! {
+                    _ = &mut shutdown, if !stopping => {
+                        stopping = true;
+                        let _ = stop.send(true);
+                    }
+                    finished = tasks.join_next() => match finished {
+                        None => break,
+                        Some(Ok(Ok(()))) => {}
+                        Some(Ok(Err(err))) => {
+                            error!("Polling of a community stopped: {err}");
+                            if result.is_ok() {
+                                result = Err(err);
+                            }
+                        }
+                        Some(Err(err)) => error!("Polling of a community panicked: {err}"),
+                    },
+                }
+            }
+
+            result
         }))
     }
 }
@@ -101,3 +596,253 @@ impl Future for PollFuture {
         self.0.as_mut().poll(cx)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        checkpoint::MemoryCheckpoint,
+        extract::{Ctx, State},
+        test::MockApi,
+        Filter, SafeVk,
+    };
+    use serde_json::{json, Value};
+    use tokio::sync::{
+        mpsc::{self, UnboundedSender},
+        Notify,
+    };
+
+    fn batch(ts: &str, text: &str) -> Value {
+        json!({
+            "ts": ts,
+            "updates": [{
+                "type": "message_new",
+                "event_id": format!("event_{ts}"),
+                "v": crate::VERSION,
+                "object": { "message": { "text": text, "peer_id": 1, "from_id": 1 } },
+            }],
+        })
+    }
+
+    async fn fast() {}
+
+    async fn notify(State(done): State<Arc<Notify>>) -> Response<()> {
+        done.notify_one();
+        Ok(())
+    }
+
+    async fn panic() {
+        panic!("handler failed");
+    }
+
+    async fn stuck(State(started): State<Arc<Notify>>) -> Response<()> {
+        started.notify_one();
+        std::future::pending().await
+    }
+
+    /// Replies in the community the message came from and reports its id.
+    async fn who(update: Ctx<Update>, State(replied): State<UnboundedSender<u64>>) -> Response<()> {
+        let group_id = update.group_id().unwrap();
+        update
+            .request()
+            .call_method("messages.send", format!("group={group_id}&").as_bytes())
+            .await?;
+        replied.send(group_id).unwrap();
+        Ok(())
+    }
+
+    fn mock_api(group_id: u64) -> Arc<MockApi> {
+        let api = Arc::new(MockApi::default());
+        api.respond_once(
+            "groups.getById",
+            json!({ "response": { "groups": [{ "id": group_id }] } }),
+        );
+        api.respond_once(
+            "groups.getLongPollServer",
+            json!({ "response": { "key": "key", "server": "longpoll", "ts": "1" } }),
+        );
+        // No new events once the scripted batches run out
+        api.respond("longpoll", json!({ "updates": [] }));
+        api
+    }
+
+    #[test]
+    #[should_panic(expected = "greater than zero")]
+    fn zero_max_concurrent_handlers_panics() {
+        let _ = start_polling::<_, ()>("test", SafeVk::new()).max_concurrent_handlers(0);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn checkpoint_stops_before_unfinished_batch() {
+        let api = mock_api(1);
+        api.respond_once("longpoll", batch("2", "/fast"));
+        api.respond_once("longpoll", batch("3", "/stuck"));
+        // Done right away, but received after the unfinished one
+        api.respond_once("longpoll", json!({ "ts": "4", "updates": [] }));
+
+        let mut request = RequestBuilder::new("test");
+        request.set_mock(api);
+
+        let started = Arc::new(Notify::new());
+        let bot = SafeVk::<Arc<Notify>>::new()
+            .command("/fast", fast, Filter::Strict)
+            .command("/stuck", stuck, Filter::Strict)
+            .with_state(Arc::clone(&started));
+
+        let store = MemoryCheckpoint::new();
+        start_polling(request, bot)
+            .checkpoint(store.clone())
+            .with_graceful_shutdown(async move { started.notified().await })
+            .shutdown_timeout(Duration::from_millis(10))
+            .await
+            .unwrap();
+
+        assert_eq!(store.get().as_deref(), Some("2"));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn checkpoint_moves_past_panicked_handler() {
+        let api = mock_api(1);
+        api.respond_once("longpoll", batch("2", "/panic"));
+        api.respond_once("longpoll", batch("3", "/done"));
+
+        let mut request = RequestBuilder::new("test");
+        request.set_mock(api);
+
+        let done = Arc::new(Notify::new());
+        let bot = SafeVk::<Arc<Notify>>::new()
+            .command("/panic", panic, Filter::Strict)
+            .command("/done", notify, Filter::Strict)
+            .with_state(Arc::clone(&done));
+
+        let store = MemoryCheckpoint::new();
+        start_polling(request, bot)
+            .checkpoint(store.clone())
+            .with_graceful_shutdown(async move { done.notified().await })
+            .await
+            .unwrap();
+
+        assert_eq!(store.get().as_deref(), Some("3"));
+    }
+
+    #[tokio::test]
+    async fn permanent_errors_are_not_retried() {
+        let api = Arc::new(MockApi::default());
+        api.respond_once(
+            "groups.getById",
+            json!({ "response": { "groups": [{ "id": 1 }] } }),
+        );
+        api.respond_once(
+            "groups.getLongPollServer",
+            json!({ "error": { "error_code": 5, "error_msg": "User authorization failed" } }),
+        );
+
+        let mut request = RequestBuilder::new("test");
+        request.set_mock(api);
+
+        let result = tokio::time::timeout(
+            Duration::from_secs(5),
+            start_polling(request, SafeVk::new()).into_future(),
+        )
+        .await
+        .expect("polling kept retrying");
+
+        assert!(
+            matches!(&result, Err(Error::VkApi(err)) if err.code() == 5),
+            "{result:?}"
+        );
+    }
+
+    #[tokio::test]
+    async fn unexpected_group_response_is_an_error() {
+        let api = Arc::new(MockApi::default());
+        api.respond_once("groups.getById", json!({ "response": { "groups": [] } }));
+
+        let mut request = RequestBuilder::new("test");
+        request.set_mock(api);
+
+        let result = start_polling(request, SafeVk::new()).await;
+
+        assert!(
+            matches!(result, Err(Error::UnexpectedResponse(_))),
+            "{result:?}"
+        );
+    }
+
+    /// Tokens and groups of every `messages.send` answered by `api`.
+    fn replies(api: &MockApi) -> Vec<(String, String)> {
+        api.calls()
+            .into_iter()
+            .filter(|call| call.method == "messages.send")
+            .map(|call| (call.token.clone(), call.param("group").unwrap().to_owned()))
+            .collect()
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn every_community_replies_with_its_own_token() {
+        let (first_api, second_api) = (mock_api(1), mock_api(2));
+        first_api.respond_once("longpoll", batch("2", "/who"));
+        second_api.respond_once("longpoll", batch("2", "/who"));
+
+        let mut first = RequestBuilder::new("first");
+        first.set_mock(Arc::clone(&first_api));
+        let mut second = RequestBuilder::new("second");
+        second.set_mock(Arc::clone(&second_api));
+
+        let (replied, mut groups) = mpsc::unbounded_channel();
+        let bot = SafeVk::<UnboundedSender<u64>>::new()
+            .command("/who", who, Filter::Strict)
+            .with_state(replied);
+
+        MultiPoller::new()
+            .add(first, bot.clone())
+            .add(second, bot)
+            .with_graceful_shutdown(async move {
+                groups.recv().await;
+                groups.recv().await;
+            })
+            .await
+            .unwrap();
+
+        assert_eq!(replies(&first_api), [("first".to_owned(), "1".to_owned())]);
+        assert_eq!(
+            replies(&second_api),
+            [("second".to_owned(), "2".to_owned())]
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn failed_community_does_not_stop_the_others() {
+        let working_api = mock_api(1);
+        working_api.respond_once("longpoll", batch("2", "/who"));
+        let failing_api = Arc::new(MockApi::default());
+        failing_api.respond_once("groups.getById", json!({ "response": { "groups": [] } }));
+
+        let mut working = RequestBuilder::new("working");
+        working.set_mock(Arc::clone(&working_api));
+        let mut failing = RequestBuilder::new("failing");
+        failing.set_mock(failing_api);
+
+        let (replied, mut groups) = mpsc::unbounded_channel();
+        let bot = SafeVk::<UnboundedSender<u64>>::new()
+            .command("/who", who, Filter::Strict)
+            .with_state(replied);
+
+        let result = MultiPoller::new()
+            .add(failing, bot.clone())
+            .add(working, bot)
+            .with_graceful_shutdown(async move {
+                groups.recv().await;
+            })
+            .await;
+
+        assert!(
+            matches!(result, Err(Error::UnexpectedResponse(_))),
+            "{result:?}"
+        );
+        assert_eq!(
+            replies(&working_api),
+            [("working".to_owned(), "1".to_owned())]
+        );
+    }
+}
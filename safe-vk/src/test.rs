@@ -0,0 +1,306 @@
+//! Testing routers without VK.
+//!
+//! [`TestBot`] runs a [`SafeVk`](crate::SafeVk) against a fake API: updates are handed to the
+//! router directly, every API call the handlers make is recorded instead of being sent, and
+//! the answers to them can be scripted. Requires the `test-util` feature, usually enabled only
+//! for tests:
+//!
+//! ```toml
+//! [dev-dependencies]
+//! safe-vk = { version = "*", features = ["test-util"] }
+//! ```
+//!
+//! ```ignore
+//! use safe_vk::{extract::Ctx, responses::Message, test::TestBot, Filter, Result, SafeVk};
+//!
+//! async fn start(update: Ctx<Message>) -> Result<()> {
+//!     update.messages().send().random_id(0).message("hi").await?;
+//!     Ok(())
+//! }
+//!
+//! #[tokio::test]
+//! async fn replies_to_start() {
+//!     let mut bot = TestBot::new(SafeVk::new().command("/start", start, Filter::Strict));
+//!
+//!     bot.send(TestBot::message("/start").from(1).peer(2000000001)).await.unwrap();
+//!
+//!     let calls = bot.calls();
+//!     assert_eq!(calls[0].method, "messages.send");
+//!     assert_eq!(calls[0].param("peer_id"), Some("2000000001"));
+//!     assert_eq!(calls[0].param("message"), Some("hi"));
+//! }
+//! ```
+use crate::{
+    extract::Update, reqwest_ext::query_params, responses::Event, service::Service, RequestBuilder,
+    Response, VERSION,
+};
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::{
+    collections::{HashMap, VecDeque},
+    future::poll_fn,
+    sync::{
+        atomic::{AtomicI64, Ordering},
+        Arc, Mutex,
+    },
+};
+
+/// Token of the [`RequestBuilder`] handlers of a [`TestBot`] get.
+const TOKEN: &str = "test";
+
+/// Used for `event_id`s and message ids, so every built update is unique.
+static NEXT_ID: AtomicI64 = AtomicI64::new(1);
+
+/// Runs a router with a fake API, see the [module documentation](self).
+#[derive(Debug)]
+pub struct TestBot<M> {
+    safevk: M,
+    request: Arc<RequestBuilder>,
+    api: Arc<MockApi>,
+}
+
+/// An API call made by a handler of a [`TestBot`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ApiCall {
+    /// Name of the method, e.g. `messages.send`.
+    pub method: String,
+    /// Parameters the method was called with.
+    pub params: HashMap<String, String>,
+    /// Access token the call was sent with.
+    pub token: String,
+}
+
+impl ApiCall {
+    /// Value of the `name` parameter, if it was passed.
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.params.get(name).map(String::as_str)
+    }
+}
+
+impl<M> TestBot<M>
+where
+    M: Service<Update>,
+{
+    pub fn new(safevk: M) -> Self {
+        let api = Arc::new(MockApi::default());
+        // Calls aren't sent anywhere, so there is nothing to limit
+        let mut request = RequestBuilder::builder(TOKEN)
+            .without_rate_limit()
+            .build()
+            .expect("default client");
+        request.set_mock(Arc::clone(&api));
+
+        Self {
+            safevk,
+            request: Arc::new(request),
+            api,
+        }
+    }
+
+    /// Pretends the token belongs to the community `group_id`, as if long polling has started.
+    pub fn group_id(mut self, group_id: u64) -> Self {
+        let mut request = RequestBuilder::clone(&self.request);
+        request.set_group_id(group_id);
+        self.request = Arc::new(request);
+        self
+    }
+
+    /// Hands `update` to the router and waits until its handler is done.
+    pub async fn send(&mut self, update: impl Into<Update>) -> Response<()> {
+        poll_fn(|cx| self.safevk.poll_ready(cx)).await?;
+        self.safevk
+            .call(update.into(), Arc::clone(&self.request))
+            .await
+            .map(|_| ())
+    }
+
+    /// Answers every call of `method` with `response`, which becomes the `response` field.
+    ///
+    /// Methods without a scripted response return `1`.
+    pub fn respond(&self, method: &str, response: impl Serialize) -> &Self {
+        let response = json!({ "response": response });
+        self.api.respond(method, response);
+        self
+    }
+
+    /// Answers the next call of `method` with `response`. Such answers are used in the order
+    /// they were added, before the one set by [`respond`](Self::respond).
+    pub fn respond_once(&self, method: &str, response: impl Serialize) -> &Self {
+        self.api
+            .respond_once(method, json!({ "response": response }));
+        self
+    }
+
+    /// Answers the next call of `method` with a VK error.
+    pub fn respond_error(&self, method: &str, code: i32, message: &str) -> &Self {
+        let response = json!({ "error": { "error_code": code, "error_msg": message } });
+        self.api
+            .script(method, |script| script.once.push_back(response));
+        self
+    }
+
+    /// Every API call made so far, in order.
+    pub fn calls(&self) -> Vec<ApiCall> {
+        self.api.calls()
+    }
+
+    /// API calls of `method` made so far, in order.
+    pub fn calls_of(&self, method: &str) -> Vec<ApiCall> {
+        self.api
+            .calls
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|call| call.method == method)
+            .cloned()
+            .collect()
+    }
+
+    /// Forgets the API calls made so far, scripted responses are kept.
+    pub fn clear_calls(&self) {
+        self.api.calls.lock().unwrap().clear();
+    }
+}
+
+impl TestBot<()> {
+    /// A `message_new` update with `text`, sent by user `1` in a direct conversation.
+    pub fn message(text: impl Into<String>) -> TestMessage {
+        TestMessage {
+            text: text.into(),
+            from_id: 1,
+            peer_id: None,
+            payload: None,
+        }
+    }
+
+    /// An update of any `update_type` with the given `object`.
+    pub fn event(update_type: impl Into<String>, object: impl Serialize) -> Update {
+        Event {
+            update_type: update_type.into(),
+            event_id: next_event_id(),
+            v: VERSION.to_owned(),
+            object: json!(object),
+        }
+    }
+}
+
+/// A `message_new` update built by [`TestBot::message`].
+#[derive(Debug, Clone)]
+pub struct TestMessage {
+    text: String,
+    from_id: i64,
+    peer_id: Option<i64>,
+    payload: Option<String>,
+}
+
+impl TestMessage {
+    /// Sender of the message.
+    pub fn from(mut self, from_id: i64) -> Self {
+        self.from_id = from_id;
+        self
+    }
+
+    /// Conversation the message was sent to, the sender unless set.
+    pub fn peer(mut self, peer_id: i64) -> Self {
+        self.peer_id = Some(peer_id);
+        self
+    }
+
+    /// Payload of the button that sent the message.
+    pub fn payload(mut self, payload: impl Serialize) -> Self {
+        self.payload = Some(json!(payload).to_string());
+        self
+    }
+}
+
+impl From<TestMessage> for Update {
+    fn from(message: TestMessage) -> Self {
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        let object = json!({
+            "message": {
+                "id": id,
+                "date": 0,
+                "peer_id": message.peer_id.unwrap_or(message.from_id),
+                "from_id": message.from_id,
+                "text": message.text,
+                "random_id": 0,
+                "attachments": [],
+                "important": false,
+                "payload": message.payload,
+                "fwd_messages": [],
+                "conversation_message_id": id,
+                "out": 0,
+            },
+            "client_info": null,
+        });
+
+        Event {
+            update_type: "message_new".to_owned(),
+            event_id: format!("test_{id}"),
+            v: VERSION.to_owned(),
+            object,
+        }
+    }
+}
+
+fn next_event_id() -> String {
+    format!("test_{}", NEXT_ID.fetch_add(1, Ordering::Relaxed))
+}
+
+/// Stands in for the VK API of a [`TestBot`].
+#[derive(Debug, Default)]
+pub(crate) struct MockApi {
+    calls: Mutex<Vec<ApiCall>>,
+    responses: Mutex<HashMap<String, Script>>,
+}
+
+#[derive(Debug, Default)]
+struct Script {
+    once: VecDeque<Value>,
+    always: Option<Value>,
+}
+
+impl MockApi {
+    fn script(&self, method: &str, f: impl FnOnce(&mut Script)) {
+        f(self
+            .responses
+            .lock()
+            .unwrap()
+            .entry(method.to_owned())
+            .or_default());
+    }
+
+    /// Answers every call of `method` without a response of its own with `response` as is.
+    pub(crate) fn respond(&self, method: &str, response: Value) {
+        self.script(method, |script| script.always = Some(response));
+    }
+
+    /// Answers the next call of `method` with `response` as is.
+    pub(crate) fn respond_once(&self, method: &str, response: Value) {
+        self.script(method, |script| script.once.push_back(response));
+    }
+
+    /// Every call answered so far, in order.
+    pub(crate) fn calls(&self) -> Vec<ApiCall> {
+        self.calls.lock().unwrap().clone()
+    }
+
+    /// Records a call of `method` with `query` sent with `token` and answers it, errors are
+    /// handled by the caller as if VK sent them.
+    pub(crate) fn answer(&self, method: &str, query: &str, token: &str) -> Value {
+        self.calls.lock().unwrap().push(ApiCall {
+            method: method.to_owned(),
+            params: query_params(query)
+                .map(|(key, value)| (key.to_owned(), value.into_owned()))
+                .collect(),
+            token: token.to_owned(),
+        });
+
+        self.responses
+            .lock()
+            .unwrap()
+            .get_mut(method)
+            .and_then(|script| script.once.pop_front().or_else(|| script.always.clone()))
+            .unwrap_or_else(|| json!({ "response": 1 }))
+    }
+}
@@ -0,0 +1,192 @@
+use crate::{
+    rate_limit::{RateLimit, RateLimiter},
+    reqwest_ext::REDACTED,
+};
+use std::{
+    fmt,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+/// How long a token stays out of rotation by default.
+const COOL_DOWN: Duration = Duration::from_secs(600);
+
+/// Several access tokens of the same community that API method calls are spread across,
+/// see [`RequestConfig::token_pool`](crate::RequestConfig::token_pool).
+///
+/// Tokens are used in turns, each with its own [`RateLimit`]. A token that VK reports as
+/// [invalid](crate::VkError::InvalidCommunityAccessToken) or
+/// [out of calls](crate::VkError::MethodCallLimitReached) is taken out of rotation for the
+/// [`cool_down`](Self::cool_down) period and the call is repeated with the next one.
+///
+/// Long poll always stays on the token the [`RequestBuilder`](crate::RequestBuilder) was
+/// created with.
+///
+/// ```ignore
+/// let request = RequestBuilder::builder(&main_token)
+///     .token_pool(TokenPool::new([token_1, token_2, token_3]))
+///     .build()?;
+/// ```
+#[derive(Debug)]
+pub struct TokenPool {
+    tokens: Vec<PooledToken>,
+    next: AtomicUsize,
+    cool_down: Duration,
+}
+
+pub(crate) struct PooledToken {
+    token: String,
+    limiter: Option<RateLimiter>,
+    cooling_until: Mutex<Option<Instant>>,
+}
+
+impl TokenPool {
+    pub fn new<T: Into<String>>(tokens: impl IntoIterator<Item = T>) -> Self {
+        Self {
+            tokens: tokens
+                .into_iter()
+                .map(|token| PooledToken {
+                    token: token.into(),
+                    limiter: None,
+                    cooling_until: Mutex::new(None),
+                })
+                .collect(),
+            next: AtomicUsize::new(0),
+            cool_down: COOL_DOWN,
+        }
+    }
+
+    /// How long a failed token stays out of rotation, 10 minutes by default.
+    pub fn cool_down(mut self, cool_down: Duration) -> Self {
+        self.cool_down = cool_down;
+        self
+    }
+
+    /// Limits every token on its own.
+    pub(crate) fn rate_limit(mut self, limit: Option<RateLimit>) -> Self {
+        for token in &mut self.tokens {
+            token.limiter = limit.map(RateLimiter::new);
+        }
+        self
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.tokens.len()
+    }
+
+    /// Picks the next token that isn't cooling down. If all of them are, the one that
+    /// comes back first is used anyway, since there is nothing better to try.
+    pub(crate) fn next(&self) -> Option<&PooledToken> {
+        let now = Instant::now();
+        let start = self.next.fetch_add(1, Ordering::Relaxed);
+
+        (0..self.tokens.len())
+            .map(|i| &self.tokens[(start + i) % self.tokens.len()])
+            .find(|token| !matches!(token.available_at(), Some(at) if at > now))
+            .or_else(|| self.tokens.iter().min_by_key(|token| token.available_at()))
+    }
+
+    /// Takes `token` out of rotation for the cool-down period.
+    pub(crate) fn cool(&self, token: &PooledToken) {
+        *token.cooling_until.lock().unwrap() = Some(Instant::now() + self.cool_down);
+    }
+}
+
+impl fmt::Debug for PooledToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PooledToken")
+            .field("token", &REDACTED)
+            .field("limiter", &self.limiter)
+            .field("cooling_until", &self.cooling_until)
+            .finish()
+    }
+}
+
+impl PooledToken {
+    pub(crate) fn token(&self) -> &str {
+        &self.token
+    }
+
+    /// Waits until one more request can be made with this token.
+    pub(crate) async fn acquire(&self) {
+        if let Some(limiter) = &self.limiter {
+            limiter.acquire().await;
+        }
+    }
+
+    fn available_at(&self) -> Option<Instant> {
+        *self.cooling_until.lock().unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{test::MockApi, RequestBuilder};
+    use serde_json::json;
+    use std::sync::Arc;
+
+    fn error(code: i32) -> serde_json::Value {
+        json!({ "error": { "error_code": code, "error_msg": "token failed" } })
+    }
+
+    #[tokio::test]
+    async fn failed_token_cools_and_next_one_is_used() {
+        let api = Arc::new(MockApi::default());
+        api.respond_once("messages.send", error(29));
+        api.respond_once("users.get", error(27));
+        let mut request = RequestBuilder::builder("main")
+            .token_pool(TokenPool::new(["first", "second", "third"]))
+            .without_rate_limit()
+            .build()
+            .unwrap();
+        request.set_mock(Arc::clone(&api));
+
+        // `first` is out of calls, `second` takes over
+        request
+            .call_method("messages.send", b"peer_id=1&")
+            .await
+            .unwrap();
+        // `third` is invalid, `second` is the only one left
+        request.call_method("users.get", b"").await.unwrap();
+        request.call_method("users.get", b"").await.unwrap();
+
+        let tokens = api
+            .calls()
+            .into_iter()
+            .map(|call| call.token)
+            .collect::<Vec<_>>();
+        assert_eq!(tokens, ["first", "second", "third", "second", "second"]);
+    }
+
+    #[tokio::test]
+    async fn error_is_returned_once_every_token_failed() {
+        let api = Arc::new(MockApi::default());
+        api.respond("messages.send", error(29));
+        let mut request = RequestBuilder::builder("main")
+            .token_pool(TokenPool::new(["first", "second"]))
+            .without_rate_limit()
+            .build()
+            .unwrap();
+        request.set_mock(Arc::clone(&api));
+
+        let result = request.call_method("messages.send", b"peer_id=1&").await;
+
+        assert!(matches!(
+            result,
+            Err(crate::Error::VkApi(crate::VkError::MethodCallLimitReached(
+                _
+            )))
+        ));
+        // With every token cooling, the one that comes back first is tried anyway
+        let tokens = api
+            .calls()
+            .into_iter()
+            .map(|call| call.token)
+            .collect::<Vec<_>>();
+        assert_eq!(tokens, ["first", "second", "first"]);
+    }
+}
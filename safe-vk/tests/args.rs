@@ -0,0 +1,60 @@
+use safe_vk::{
+    extract::{ArgsError, FromArgs},
+    FromArgs,
+};
+
+#[derive(Debug, PartialEq, FromArgs)]
+struct Ban {
+    user: i64,
+    reason: Option<String>,
+}
+
+#[derive(Debug, PartialEq, FromArgs)]
+struct Resize(u32, Option<u32>);
+
+fn args(args: &[&str]) -> Vec<String> {
+    args.iter().map(|arg| arg.to_string()).collect()
+}
+
+#[test]
+fn derived_usage_names_fields() {
+    assert_eq!(Ban::usage(), "<user> [reason]");
+    assert_eq!(Resize::usage(), "<u32> [u32]");
+}
+
+#[test]
+fn derived_from_args() {
+    assert_eq!(
+        Ban::from_args(&args(&["123", "for spam"])),
+        Ok(Ban {
+            user: 123,
+            reason: Some("for spam".to_owned())
+        })
+    );
+    assert_eq!(
+        Ban::from_args(&args(&["123"])),
+        Ok(Ban {
+            user: 123,
+            reason: None
+        })
+    );
+    assert_eq!(Resize::from_args(&args(&["640"])), Ok(Resize(640, None)));
+}
+
+#[test]
+fn derived_from_args_errors() {
+    assert_eq!(
+        Ban::from_args(&args(&[])),
+        Err(ArgsError::Missing {
+            name: "user".to_owned()
+        })
+    );
+    assert_eq!(
+        Ban::from_args(&args(&["123", "spam", "again"])),
+        Err(ArgsError::TooMany { expected: 2 })
+    );
+    assert!(matches!(
+        Ban::from_args(&args(&["someone"])),
+        Err(ArgsError::Invalid { name, .. }) if name == "user"
+    ));
+}
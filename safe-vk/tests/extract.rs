@@ -0,0 +1,90 @@
+use safe_vk::{
+    extract::{Captures, Ctx, Keyboard},
+    responses::Message,
+    test::TestBot,
+    Error, Result, SafeVk,
+};
+use serde::Deserialize;
+use serde_json::json;
+
+async fn greet(update: Ctx<Message>) -> Result<()> {
+    update.messages().send().random_id(0).message("hi").await?;
+    Ok(())
+}
+
+#[derive(Deserialize)]
+struct Resize {
+    width: u32,
+    height: Option<u32>,
+}
+
+async fn resize(update: Ctx<Message>, Captures(size): Captures<Resize>) -> Result<()> {
+    let text = format!("{}x{}", size.width, size.height.unwrap_or(size.width));
+    update.messages().send().random_id(0).message(&text).await?;
+    Ok(())
+}
+
+#[derive(Deserialize)]
+struct Page {
+    #[allow(dead_code)]
+    page: u32,
+}
+
+async fn turn_page(_keyboard: Keyboard<Page>) -> Result<()> {
+    Ok(())
+}
+
+#[tokio::test]
+async fn ctx_rejects_an_update_of_another_type() {
+    let mut bot = TestBot::new(SafeVk::new().on_group_join(greet));
+
+    let result = bot
+        .send(TestBot::event(
+            "group_join",
+            json!({ "user_id": 1, "join_type": "join" }),
+        ))
+        .await;
+
+    match result {
+        Err(Error::Rejection(message)) => assert!(message.contains("`group_join`"), "{message}"),
+        other => panic!("expected a rejection, got {other:?}"),
+    }
+    assert!(bot.calls().is_empty());
+}
+
+#[tokio::test]
+async fn keyboard_rejects_a_mismatched_payload() {
+    let mut bot = TestBot::new(SafeVk::new().on_message_event(turn_page));
+
+    let result = bot
+        .send(TestBot::event(
+            "message_event",
+            json!({
+                "conversation_message_id": 1,
+                "event_id": "abc",
+                "payload": { "page": "next" },
+                "peer_id": 1,
+                "user_id": 1,
+            }),
+        ))
+        .await;
+
+    assert!(matches!(result, Err(Error::Rejection(_))), "{result:?}");
+}
+
+#[tokio::test]
+async fn captures_come_from_the_matched_route() {
+    let mut bot = TestBot::new(
+        SafeVk::new().command_regex(r"^/resize (?P<width>\d+)(x(?P<height>\d+))?$", resize),
+    );
+
+    bot.send(TestBot::message("/resize 640x480")).await.unwrap();
+    bot.send(TestBot::message("/resize 128")).await.unwrap();
+
+    let sent: Vec<_> = bot
+        .calls_of("messages.send")
+        .iter()
+        .filter_map(|call| call.param("message").map(str::to_owned))
+        .collect();
+    assert_eq!(sent, ["640x480", "128x128"]);
+}
@@ -0,0 +1,238 @@
+use safe_vk::{
+    extract::{Args, Ctx},
+    responses::Message,
+    test::TestBot,
+    Error, Filter, Result, SafeVk,
+};
+
+async fn reply(update: &Ctx<Message>, text: &str) -> Result<()> {
+    update.messages().send().random_id(0).message(text).await?;
+    Ok(())
+}
+
+async fn start(update: Ctx<Message>) -> Result<()> {
+    reply(&update, "start").await
+}
+
+async fn ban(update: Ctx<Message>) -> Result<()> {
+    reply(&update, "ban").await
+}
+
+async fn fallback(update: Ctx<Message>) -> Result<()> {
+    reply(&update, "fallback").await
+}
+
+async fn seed(update: Ctx<Message>, Args((seed,)): Args<(u16,)>) -> Result<()> {
+    reply(&update, &format!("seed {seed}")).await
+}
+
+async fn echo(update: Ctx<Message>, Args((text,)): Args<(String,)>) -> Result<()> {
+    reply(&update, &text).await
+}
+
+async fn decline(_update: Ctx<Message>) -> Result<()> {
+    Err(Error::Continue)
+}
+
+async fn watch(update: Ctx<Message>) -> Result<()> {
+    reply(&update, "watch").await
+}
+
+async fn inspect(update: Ctx<Message>) -> Result<()> {
+    reply(&update, &format!("inspect {}", update.message.text)).await
+}
+
+/// Texts of the messages sent so far.
+fn sent(bot: &TestBot<SafeVk>) -> Vec<String> {
+    bot.calls_of("messages.send")
+        .iter()
+        .filter_map(|call| call.param("message").map(str::to_owned))
+        .collect()
+}
+
+#[tokio::test]
+async fn commands_match_by_filter() {
+    let mut bot = TestBot::new(
+        SafeVk::new()
+            .command("/start", start, Filter::Strict)
+            .command("/seed", seed, Filter::Prefix),
+    );
+
+    bot.send(TestBot::message("/start")).await.unwrap();
+    bot.send(TestBot::message("/start now")).await.unwrap();
+    bot.send(TestBot::message("/seed 42")).await.unwrap();
+    bot.send(TestBot::message("/unknown")).await.unwrap();
+
+    assert_eq!(sent(&bot), ["start", "seed 42"]);
+}
+
+#[tokio::test]
+async fn prefix_filter_needs_trigger_at_start() {
+    let mut bot = TestBot::new(SafeVk::new().command("/seed", seed, Filter::Prefix));
+
+    bot.send(TestBot::message("what does /seed do?"))
+        .await
+        .unwrap();
+    bot.send(TestBot::message("/seeds 42")).await.unwrap();
+    bot.send(TestBot::message("/seed\t7")).await.unwrap();
+
+    assert_eq!(sent(&bot), ["seed 7"]);
+}
+
+#[tokio::test]
+async fn invalid_args_reply_with_usage() {
+    let mut bot = TestBot::new(SafeVk::new().command("/seed", seed, Filter::Prefix));
+
+    let result = bot.send(TestBot::message("/seed abc").peer(7)).await;
+
+    assert!(matches!(result, Err(Error::Rejection(_))));
+    let calls = bot.calls_of("messages.send");
+    assert_eq!(calls.len(), 1);
+    assert_eq!(calls[0].param("peer_id"), Some("7"));
+    let text = calls[0].param("message").unwrap();
+    assert!(text.starts_with("Invalid argument"), "{text}");
+    assert!(text.ends_with("Usage: /seed <u16>"), "{text}");
+}
+
+#[tokio::test]
+async fn message_text_keeps_query_separators() {
+    let mut bot = TestBot::new(SafeVk::new().command("/echo", echo, Filter::Prefix));
+
+    bot.send(TestBot::message(r#"/echo "a&b=c 100%""#))
+        .await
+        .unwrap();
+
+    assert_eq!(sent(&bot), ["a&b=c 100%"]);
+}
+
+#[tokio::test]
+async fn nest_moves_commands_under_prefix() {
+    let admin = SafeVk::new().command("/ban", ban, Filter::Strict);
+    let mut bot = TestBot::new(
+        SafeVk::new()
+            .command("/start", start, Filter::Strict)
+            .nest("/admin", admin)
+            .unwrap(),
+    );
+
+    bot.send(TestBot::message("/ban")).await.unwrap();
+    bot.send(TestBot::message("/admin ban")).await.unwrap();
+    bot.send(TestBot::message("/start")).await.unwrap();
+
+    assert_eq!(sent(&bot), ["ban", "start"]);
+}
+
+#[tokio::test]
+async fn merge_adds_routes_of_both_routers() {
+    let other: SafeVk = SafeVk::new().command("/ban", ban, Filter::Strict);
+    let mut bot = TestBot::new(
+        SafeVk::new()
+            .command("/start", start, Filter::Strict)
+            .merge(other)
+            .unwrap(),
+    );
+
+    bot.send(TestBot::message("/ban")).await.unwrap();
+    bot.send(TestBot::message("/start")).await.unwrap();
+
+    assert_eq!(sent(&bot), ["ban", "start"]);
+}
+
+#[tokio::test]
+async fn merge_rejects_the_same_route() {
+    let other: SafeVk = SafeVk::new().command("/start", ban, Filter::Strict);
+    let merged = SafeVk::new()
+        .command("/start", start, Filter::Strict)
+        .merge(other);
+
+    assert!(matches!(merged, Err(Error::RouteCollision(_))));
+}
+
+#[tokio::test]
+async fn merge_rejects_the_same_trigger_with_another_filter() {
+    let other: SafeVk = SafeVk::new().command("/start", ban, Filter::Sensitive);
+    let merged = SafeVk::new()
+        .command("/start", start, Filter::Strict)
+        .merge(other);
+
+    assert!(matches!(merged, Err(Error::RouteCollision(route)) if route == "/start"));
+}
+
+#[tokio::test]
+async fn nest_rejects_the_same_trigger_with_another_filter() {
+    let admin: SafeVk = SafeVk::new().command("/ban", ban, Filter::Flexible);
+    let nested = SafeVk::new()
+        .command("/admin ban", start, Filter::Strict)
+        .nest("/admin", admin);
+
+    assert!(matches!(nested, Err(Error::RouteCollision(_))));
+}
+
+#[tokio::test]
+async fn continue_falls_through_to_the_next_route() {
+    let mut bot = TestBot::new(
+        SafeVk::new()
+            .command("/start", decline, Filter::Strict)
+            .on("message_new", fallback),
+    );
+
+    bot.send(TestBot::message("/start")).await.unwrap();
+
+    assert_eq!(sent(&bot), ["fallback"]);
+}
+
+#[tokio::test]
+async fn continue_falls_through_every_kind_of_route() {
+    let mut bot = TestBot::new(
+        SafeVk::new()
+            .on("message_new", fallback)
+            .command_regex(r"^/start$", decline)
+            .command("/start", decline, Filter::Strict),
+    );
+
+    bot.send(TestBot::message("/start")).await.unwrap();
+
+    assert_eq!(sent(&bot), ["fallback"]);
+}
+
+#[tokio::test]
+async fn watchers_only_get_updates_no_route_took() {
+    let mut bot = TestBot::new(
+        SafeVk::new()
+            .command("/start", start, Filter::Strict)
+            .command("/ban", decline, Filter::Strict)
+            .watch(watch),
+    );
+
+    bot.send(TestBot::message("/start")).await.unwrap();
+    bot.send(TestBot::message("/ban")).await.unwrap();
+    bot.send(TestBot::message("hello")).await.unwrap();
+
+    assert_eq!(sent(&bot), ["start", "watch", "watch"]);
+}
+
+#[tokio::test]
+async fn inspectors_get_every_update() {
+    let mut bot = TestBot::new(
+        SafeVk::new()
+            .command("/start", start, Filter::Strict)
+            .inspect(inspect)
+            .watch(watch),
+    );
+
+    bot.send(TestBot::message("/start")).await.unwrap();
+    bot.send(TestBot::message("hello")).await.unwrap();
+
+    assert_eq!(
+        sent(&bot),
+        ["start", "inspect /start", "watch", "inspect hello"]
+    );
+}
+
+#[test]
+#[should_panic(expected = "greater than zero")]
+fn zero_concurrency_limit_panics() {
+    let _ = SafeVk::<()>::new()
+        .command("/start", start, Filter::Strict)
+        .concurrency_limit(0);
+}